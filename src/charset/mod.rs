@@ -0,0 +1,51 @@
+// ZX81 character set helpers
+// Codes 0x00-0x3F are printable, bit 7 selects inverse video. Codes 0x01-0x0A
+// are block graphics with no ASCII equivalent.
+#[rustfmt::skip]
+const ZX81_CHARS: [Option<char>; 64] = [
+    Some(' '), None, None, None, None, None, None, None, // 0x00-0x07
+    None, None, None, Some('"'), Some('£'), Some('$'), Some(':'), Some('?'), // 0x08-0x0F
+    Some('('), Some(')'), Some('>'), Some('<'), Some('='), Some('+'), Some('-'), Some('*'), // 0x10-0x17
+    Some('/'), Some(';'), Some(','), Some('.'), Some('0'), Some('1'), Some('2'), Some('3'), // 0x18-0x1F
+    Some('4'), Some('5'), Some('6'), Some('7'), Some('8'), Some('9'), Some('A'), Some('B'), // 0x20-0x27
+    Some('C'), Some('D'), Some('E'), Some('F'), Some('G'), Some('H'), Some('I'), Some('J'), // 0x28-0x2F
+    Some('K'), Some('L'), Some('M'), Some('N'), Some('O'), Some('P'), Some('Q'), Some('R'), // 0x30-0x37
+    Some('S'), Some('T'), Some('U'), Some('V'), Some('W'), Some('X'), Some('Y'), Some('Z'), // 0x38-0x3F
+];
+
+pub const INVERSE: u8 = 0x80;
+
+pub fn to_char(code: u8) -> Option<char> {
+    if code & 0x7F >= 0x40 {
+        return None;
+    }
+    ZX81_CHARS[(code & 0x3F) as usize]
+}
+
+pub fn from_char(ch: char) -> Option<u8> {
+    let ch = ch.to_ascii_uppercase();
+    ZX81_CHARS
+        .iter()
+        .position(|&c| c == Some(ch))
+        .map(|code| code as u8)
+}
+
+// Names on tape end with their last character in inverse video
+pub fn decode_name(bytes: &[u8]) -> String {
+    let mut name = String::new();
+    for &byte in bytes {
+        name.push(to_char(byte).unwrap_or('?'));
+        if byte & INVERSE != 0 {
+            break;
+        }
+    }
+    name
+}
+
+pub fn encode_name(name: &str) -> Vec<u8> {
+    let mut bytes: Vec<u8> = name.chars().filter_map(from_char).collect();
+    if let Some(last) = bytes.last_mut() {
+        *last |= INVERSE;
+    }
+    bytes
+}
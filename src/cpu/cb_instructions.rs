@@ -33,7 +33,7 @@ impl Cpu {
         self.set_flag_h(false);
         self.set_flag_z(result == 0);
         self.set_flag_s((result & 0x80) != 0);
-        self.set_flag_pv(result.count_ones() % 2 == 0);
+        self.set_flag_pv(result.count_ones().is_multiple_of(2));
         self.set_flag_x((result & 0x20) != 0);
         self.set_flag_y((result & 0x08) != 0);
 
@@ -52,7 +52,7 @@ impl Cpu {
         self.set_flag_h(false);
         self.set_flag_z(result == 0);
         self.set_flag_s((result & 0x80) != 0);
-        self.set_flag_pv(result.count_ones() % 2 == 0);
+        self.set_flag_pv(result.count_ones().is_multiple_of(2));
         self.set_flag_x((result & 0x20) != 0);
         self.set_flag_y((result & 0x08) != 0);
 
@@ -72,7 +72,7 @@ impl Cpu {
         self.set_flag_h(false);
         self.set_flag_z(result == 0);
         self.set_flag_s((result & 0x80) != 0);
-        self.set_flag_pv(result.count_ones() % 2 == 0);
+        self.set_flag_pv(result.count_ones().is_multiple_of(2));
         self.set_flag_x((result & 0x20) != 0);
         self.set_flag_y((result & 0x08) != 0);
 
@@ -92,7 +92,7 @@ impl Cpu {
         self.set_flag_h(false);
         self.set_flag_z(result == 0);
         self.set_flag_s((result & 0x80) != 0);
-        self.set_flag_pv(result.count_ones() % 2 == 0);
+        self.set_flag_pv(result.count_ones().is_multiple_of(2));
         self.set_flag_x((result & 0x20) != 0);
         self.set_flag_y((result & 0x08) != 0);
 
@@ -111,7 +111,7 @@ impl Cpu {
         self.set_flag_h(false);
         self.set_flag_z(result == 0);
         self.set_flag_s((result & 0x80) != 0);
-        self.set_flag_pv(result.count_ones() % 2 == 0);
+        self.set_flag_pv(result.count_ones().is_multiple_of(2));
         self.set_flag_x((result & 0x20) != 0);
         self.set_flag_y((result & 0x08) != 0);
 
@@ -131,7 +131,7 @@ impl Cpu {
         self.set_flag_h(false);
         self.set_flag_z(result == 0);
         self.set_flag_s((result & 0x80) != 0);
-        self.set_flag_pv(result.count_ones() % 2 == 0);
+        self.set_flag_pv(result.count_ones().is_multiple_of(2));
         self.set_flag_x((result & 0x20) != 0);
         self.set_flag_y((result & 0x08) != 0);
 
@@ -150,7 +150,7 @@ impl Cpu {
         self.set_flag_h(false);
         self.set_flag_z(result == 0);
         self.set_flag_s(false);
-        self.set_flag_pv(result.count_ones() % 2 == 0);
+        self.set_flag_pv(result.count_ones().is_multiple_of(2));
         self.set_flag_x((result & 0x20) != 0);
         self.set_flag_y((result & 0x08) != 0);
 
@@ -6,6 +6,7 @@ impl Cpu {
         match opcode {
             0x21 => self.ld_iy_nn(memory),
             0x36 => self.ld_iy_d_n(memory),
+            0x34 => self.inc_iy_d(memory),
             0x35 => self.dec_iy_d(memory),
             0x46 | 0x4E | 0x56 | 0x5E | 0x66 | 0x6E | 0x7E => self.ld_r_iy_d(opcode, memory),
             0x70 | 0x71 | 0x72 | 0x73 | 0x74 | 0x75 | 0x77 => self.ld_iy_d_r(opcode, memory),
@@ -37,6 +38,24 @@ impl Cpu {
         19
     }
 
+    fn inc_iy_d(&mut self, memory: &mut Memory) -> u8 {
+        let d = self.fetch_byte(memory) as i8;
+        let addr = self.iy.wrapping_add(d as u16);
+        let old_val = memory.read(addr);
+        let new_val = old_val.wrapping_add(1);
+        memory.write(addr, new_val);
+
+        self.set_flag_n(false);
+        self.set_flag_z(new_val == 0);
+        self.set_flag_s((new_val & 0x80) != 0);
+        self.set_flag_h((old_val & 0x0F) == 0x0F);
+        self.set_flag_pv(old_val == 0x7F);
+        self.set_flag_x((new_val & 0x20) != 0);
+        self.set_flag_y((new_val & 0x08) != 0);
+
+        23
+    }
+
     fn dec_iy_d(&mut self, memory: &mut Memory) -> u8 {
         let d = self.fetch_byte(memory) as i8;
         let addr = self.iy.wrapping_add(d as u16);
@@ -104,7 +123,7 @@ impl Cpu {
         self.set_flag_s((self.a & 0x80) != 0);
         self.set_flag_z(self.a == 0);
         self.set_flag_h(false);
-        self.set_flag_pv(self.a.count_ones() % 2 == 0);
+        self.set_flag_pv(self.a.count_ones().is_multiple_of(2));
         self.set_flag_n(false);
         self.set_flag_c(false);
         self.set_flag_x((self.a & 0x20) != 0);
@@ -1,7 +1,7 @@
 use super::Cpu;
 use crate::io::IoController;
 use crate::memory::Memory;
-use crate::tape::Tape;
+use crate::tape::TapeDeck;
 
 // Further implementation of Cpu with opcode functions
 impl Cpu {
@@ -10,13 +10,13 @@ impl Cpu {
         opcode: u8,
         memory: &mut Memory,
         io: &mut IoController,
-        tape: &Option<Tape>,
+        deck: &TapeDeck,
     ) -> u8 {
         match opcode {
             // ED-prefixed instructions
             0xED => {
                 let sub_opcode = self.fetch_byte(memory);
-                self.execute_ed_instruction(sub_opcode, memory, io, tape)
+                self.execute_ed_instruction(sub_opcode, memory, io, deck)
             }
             // CB-prefixed instructions
             0xCB => {
@@ -92,7 +92,7 @@ impl Cpu {
             0xF3 => self.di(),
             0xFB => self.ei(),
             0xD3 => self.out_n_a(memory, io),
-            0xDB => self.in_a_n(memory, io, tape),
+            0xDB => self.in_a_n(memory, io, deck),
             0xC7 | 0xCF | 0xD7 | 0xDF | 0xE7 | 0xEF | 0xF7 | 0xFF => self.rst_nn(opcode, memory),
             0x09 | 0x19 | 0x29 | 0x39 => self.add_hl_rr(opcode),
             0xEB => self.ex_de_hl(),
@@ -204,7 +204,7 @@ impl Cpu {
         self.set_flag_s((self.a & 0x80) != 0);
         self.set_flag_z(self.a == 0);
         self.set_flag_h((correction & 0x06) != 0);
-        self.set_flag_pv(self.a.count_ones() % 2 == 0);
+        self.set_flag_pv(self.a.count_ones().is_multiple_of(2));
         self.set_flag_c(carry);
         self.set_flag_x((self.a & 0x20) != 0);
         self.set_flag_y((self.a & 0x08) != 0);
@@ -293,9 +293,9 @@ impl Cpu {
         io.write_port(port, self.a);
         11
     }
-    fn in_a_n(&mut self, memory: &Memory, io: &mut IoController, tape: &Option<Tape>) -> u8 {
+    fn in_a_n(&mut self, memory: &Memory, io: &mut IoController, deck: &TapeDeck) -> u8 {
         let port = self.fetch_byte(memory);
-        self.a = io.read_port(port, self.a, tape);
+        self.a = io.read_port(port, self.a, deck);
         11
     }
     fn ld_rr_indirect_a(&mut self, opcode: u8, memory: &mut Memory) -> u8 {
@@ -344,7 +344,7 @@ impl Cpu {
 
         self.set_flag_c(false);
         self.set_flag_n(false);
-        self.set_flag_pv(self.a.count_ones() % 2 == 0);
+        self.set_flag_pv(self.a.count_ones().is_multiple_of(2));
         self.set_flag_h(true);
         self.set_flag_z(self.a == 0);
         self.set_flag_s((self.a & 0x80) != 0);
@@ -361,7 +361,7 @@ impl Cpu {
 
         self.set_flag_c(false);
         self.set_flag_n(false);
-        self.set_flag_pv(self.a.count_ones() % 2 == 0);
+        self.set_flag_pv(self.a.count_ones().is_multiple_of(2));
         self.set_flag_h(false);
         self.set_flag_z(self.a == 0);
         self.set_flag_s((self.a & 0x80) != 0);
@@ -378,7 +378,7 @@ impl Cpu {
 
         self.set_flag_c(false);
         self.set_flag_n(false);
-        self.set_flag_pv(self.a.count_ones() % 2 == 0);
+        self.set_flag_pv(self.a.count_ones().is_multiple_of(2));
         self.set_flag_h(false);
         self.set_flag_z(self.a == 0);
         self.set_flag_s((self.a & 0x80) != 0);
@@ -849,7 +849,7 @@ impl Cpu {
 
         self.set_flag_c(false);
         self.set_flag_n(false);
-        self.set_flag_pv(self.a.count_ones() % 2 == 0);
+        self.set_flag_pv(self.a.count_ones().is_multiple_of(2));
         self.set_flag_h(true);
         self.set_flag_z(self.a == 0);
         self.set_flag_s((self.a & 0x80) != 0);
@@ -864,7 +864,7 @@ impl Cpu {
 
         self.set_flag_c(false);
         self.set_flag_n(false);
-        self.set_flag_pv(self.a.count_ones() % 2 == 0);
+        self.set_flag_pv(self.a.count_ones().is_multiple_of(2));
         self.set_flag_h(false);
         self.set_flag_z(self.a == 0);
         self.set_flag_s((self.a & 0x80) != 0);
@@ -879,7 +879,7 @@ impl Cpu {
 
         self.set_flag_c(false);
         self.set_flag_n(false);
-        self.set_flag_pv(self.a.count_ones() % 2 == 0);
+        self.set_flag_pv(self.a.count_ones().is_multiple_of(2));
         self.set_flag_h(false);
         self.set_flag_z(self.a == 0);
         self.set_flag_s((self.a & 0x80) != 0);
@@ -1,5 +1,5 @@
 use crate::memory::Memory;
-use crate::tape::Tape;
+use crate::tape::TapeDeck;
 
 mod cb_instructions;
 mod dd_instructions;
@@ -42,6 +42,12 @@ pub struct Cpu {
     pub is_halted: bool,
 }
 
+impl Default for Cpu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Cpu {
     pub fn new() -> Self {
         Self {
@@ -87,7 +93,7 @@ impl Cpu {
         &mut self,
         memory: &mut Memory,
         io: &mut crate::io::IoController,
-        tape: &Option<Tape>,
+        deck: &TapeDeck,
     ) -> u8 {
         // Is the system halted?
         if self.is_halted {
@@ -98,16 +104,15 @@ impl Cpu {
         // Retrieve the opcode in the memory where our program counter currently is
         // PC is incremented in fetch_byte automatically
         let opcode = self.fetch_byte(memory);
-        let cycles = self.execute(opcode, memory, io, tape);
-        cycles
+        self.execute(opcode, memory, io, deck)
     }
     fn execute(
         &mut self,
         opcode: u8,
         memory: &mut Memory,
         io: &mut crate::io::IoController,
-        tape: &Option<Tape>,
+        deck: &TapeDeck,
     ) -> u8 {
-        self.execute_instruction(opcode, memory, io, tape)
+        self.execute_instruction(opcode, memory, io, deck)
     }
 }
@@ -1,44 +1,240 @@
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
 use crate::cpu::Cpu;
-use crate::io::IoController;
-use crate::memory::Memory;
-use crate::tape::Tape;
+use crate::io::{Action, IoController, JoystickInterface, JoystickState};
+use crate::machine::{Machine, MachineConfig};
+use crate::memory::{Memory, RomInfo};
+use crate::tape::{PSysvars, Tape, TapeDeck, TapeFormat};
+use crate::traps::{TapeMode, TrapContext, TrapHandler, Traps};
 use crate::video::Video;
 
+const ERR_SP: u16 = 0x4002;
+const FLAGS: u16 = 0x4001;
+const QUICK_LOAD_BOOT_SECS: u64 = 10; // Longer than any RAM check takes
+
 pub struct Emulator {
     cpu: Cpu,
     memory: Memory,
     video: Video,
     io: IoController,
     cycles: u64,
-    pub tape: Option<Tape>,
+    rom_info: &'static RomInfo,
+    machine: Machine,
+    traps: Traps,
+    clock_hz: u64,
+    cycles_per_frame: u64,
+    frame_duration: Duration,
+    save_dir: PathBuf,
+    deck: TapeDeck,
 }
 
 impl Emulator {
-    pub fn new(rom: Vec<u8>, debug_enabled: bool, rev_video: bool) -> Result<Self, minifb::Error> {
+    // Every subsystem is built from the one config
+    pub fn new(config: MachineConfig) -> Result<Self, minifb::Error> {
+        let rom_info = config.rom_info;
+
+        // A ROM we can't trap stays on its own routines, whatever the config says
+        let mut traps = Traps::new();
+        let _ = traps.set_tape_mode(config.tape_mode, rom_info.tape_traps);
+
         Ok(Self {
             cpu: Cpu::new(),
-            memory: Memory::new(rom),
-            video: Video::new(debug_enabled, rev_video)?,
-            io: IoController::new(),
+            video: Video::new(&config.frontend, rom_info.charset_addr)?,
+            io: IoController::from_config(&config),
+            memory: Memory::new(config.rom.clone(), config.ram_size),
             cycles: 0,
-            tape: None,
+            rom_info,
+            machine: config.machine,
+            traps,
+            clock_hz: config.clock_hz,
+            cycles_per_frame: config.cycles_per_frame(),
+            frame_duration: config.frame_duration(),
+            save_dir: config.save_dir.clone(),
+            deck: TapeDeck::new(),
         })
     }
 
-    pub fn load_tape(&mut self, tape: Tape) {
-        self.tape = Some(tape);
+    // Choose between trapped fast-loading and the genuine ROM tape routines (at
+    // normal or turbo speed). ROMs we can't trap stay on their own routines.
+    pub fn set_tape_mode(&mut self, mode: TapeMode) -> Result<(), String> {
+        self.traps
+            .set_tape_mode(mode, self.rom_info.tape_traps)
+            .map_err(|e| format!("{} for {}", e, self.rom_info.name))
+    }
+
+    pub fn rom_info(&self) -> &'static RomInfo {
+        self.rom_info
+    }
+
+    pub fn machine(&self) -> Machine {
+        self.machine
+    }
+
+    pub fn tape_mode(&self) -> TapeMode {
+        self.traps.tape_mode()
+    }
+
+    pub fn clock_hz(&self) -> u64 {
+        self.clock_hz
+    }
+
+    pub fn cycles_per_frame(&self) -> u64 {
+        self.cycles_per_frame
+    }
+
+    pub fn frame_duration(&self) -> Duration {
+        self.frame_duration
+    }
+
+    pub fn register_trap(&mut self, addr: u16, handler: TrapHandler) {
+        self.traps.register(addr, handler);
+    }
+
+    pub fn unregister_trap(&mut self, addr: u16) {
+        self.traps.unregister(addr);
+    }
+
+    // Queue a tape in the deck, it plays once the ones before it have
+    pub fn load_tape(&mut self, mut tape: Tape) {
+        tape.set_clock_hz(self.clock_hz);
+        self.deck.insert(tape);
+    }
+
+    // Boot as far as the K prompt, put a .p image straight into memory and carry on
+    // as the ROM does once a LOAD finishes: from the NXTLIN line if the program was
+    // saved running, otherwise back at the prompt. Returns the system variables it
+    // loaded. On the ZX80 a .o image goes in once the ROM reaches its main loop,
+    // which is where its LOAD finishes too, and there are no such variables.
+    pub fn quick_load(&mut self, data: &[u8]) -> Result<Option<PSysvars>, String> {
+        let Some(tape_traps) = self.rom_info.tape_traps else {
+            return Err(format!("Can't quick-load on {}", self.rom_info.name));
+        };
+
+        if tape_traps.format == TapeFormat::Zx80 {
+            self.boot_to(tape_traps.resume)?;
+            self.memory
+                .load_zx80_program(data)
+                .map_err(|e| format!("Can't quick-load program: {}", e))?;
+            return Ok(None);
+        }
+        let (Some(keyboard), Some(line_run)) = (tape_traps.keyboard, tape_traps.line_run) else {
+            return Err(format!("Can't quick-load on {}", self.rom_info.name));
+        };
+
+        // The ROM only scans the keyboard once it's done setting up
+        self.boot_to(keyboard)?;
+
+        let sysvars = self
+            .memory
+            .load_program(data)
+            .map_err(|e| format!("Can't quick-load program: {}", e))?;
+
+        // Pretend LOAD was running as a command: FLAGS says we're executing rather
+        // than checking syntax, and SLOW/FAST returns to LINE-RUN through the
+        // error-return slot on the stack
+        let flags = self.memory.read(FLAGS);
+        self.memory.write(FLAGS, flags | 0x80);
+        let err_sp = self.memory.read_word(ERR_SP);
+        self.memory.write_word(err_sp, line_run);
+        self.cpu.sp = err_sp;
+        self.cpu.pc = tape_traps.resume;
+        Ok(Some(sysvars))
+    }
+
+    // Run from power-on until the ROM first reaches `addr`
+    fn boot_to(&mut self, addr: u16) -> Result<(), String> {
+        let boot_limit = self.cycles + QUICK_LOAD_BOOT_SECS * self.clock_hz;
+        while self.cpu.pc != addr {
+            if self.cycles > boot_limit {
+                return Err(format!("ROM didn't reach 0x{:04X}", addr));
+            }
+            self.step();
+        }
+        Ok(())
+    }
+
+    pub fn deck(&self) -> &TapeDeck {
+        &self.deck
+    }
+
+    pub fn deck_mut(&mut self) -> &mut TapeDeck {
+        &mut self.deck
     }
 
     pub fn step(&mut self) -> u8 {
-        let tape_ref = &self.tape;
-        let cycles = self.cpu.step(&mut self.memory, &mut self.io, tape_ref);
-        if let Some(t) = &mut self.tape {
-            t.advance(cycles as u64);
+        self.io.set_t_state(self.cycles);
+        let cycles = match self.run_trap() {
+            Some(cycles) => cycles,
+            None => self.cpu.step(&mut self.memory, &mut self.io, &self.deck),
+        };
+        if self.io.typist().is_typing() {
+            self.update_typist();
         }
+        let ear_reads = self.io.take_ear_reads();
+        self.deck.advance(self.cycles, cycles as u64, ear_reads);
         self.cycles += cycles as u64;
         cycles
     }
 
+    // Type text into the machine by pressing its keys, as if someone were at the
+    // keyboard: keywords go in as tokens and each key waits for the ROM to take
+    // the last. Lines end with '\n'.
+    pub fn type_text(&mut self, text: &str) -> Result<(), String> {
+        if self.rom_info.tape_traps.and_then(|t| t.keyboard).is_none() {
+            return Err(format!("Can't type into {}", self.rom_info.name));
+        }
+        self.io.typist_mut().type_text(text);
+        Ok(())
+    }
+
+    // Whether there's typed text the machine hasn't taken yet
+    pub fn is_typing(&self) -> bool {
+        self.io.typist().is_typing()
+    }
+
+    // Push the joystick about, e.g. from a gamepad or a test script. Host keys bound
+    // to the joystick work alongside whatever is set here.
+    pub fn set_joystick(&mut self, state: JoystickState) -> Result<(), String> {
+        let joystick = self
+            .io
+            .joystick_mut()
+            .ok_or("No joystick interface fitted")?;
+        joystick.set_state(state);
+        Ok(())
+    }
+
+    pub fn joystick_interface(&self) -> Option<JoystickInterface> {
+        self.io.joystick().map(|joystick| joystick.interface())
+    }
+
+    // The typist moves on each time the ROM scans the keyboard
+    fn update_typist(&mut self) {
+        if let Some(keyboard) = self.rom_info.tape_traps.and_then(|t| t.keyboard)
+            && self.cpu.pc == keyboard
+        {
+            self.io.typist_mut().keyboard_scanned(&self.memory);
+        }
+    }
+
+    // Give any trap registered at the current PC a chance to run first
+    fn run_trap(&mut self) -> Option<u8> {
+        if self.cpu.is_halted {
+            return None;
+        }
+
+        let handler = self.traps.get(self.cpu.pc)?;
+        let mut ctx = TrapContext {
+            cpu: &mut self.cpu,
+            memory: &mut self.memory,
+            deck: &mut self.deck,
+            rom: self.rom_info,
+            save_dir: &self.save_dir,
+        };
+        handler(&mut ctx)
+    }
+
     pub fn dump_system_vars(&self) {
         println!("\n=== ZX81 System Variables ===");
         let d_file = self.memory.read_word(0x400C);
@@ -48,7 +244,7 @@ impl Emulator {
         println!("VARS   (0x4010): 0x{:04X}", vars);
 
         // Check display file structure
-        if d_file >= 0x4000 && d_file < 0x8000 {
+        if (0x4000..0x8000).contains(&d_file) {
             let mut newlines = 0;
             for i in 0..800 {
                 if self.memory.read(d_file + i) == 0x76 {
@@ -68,6 +264,14 @@ impl Emulator {
         &mut self.cpu
     }
 
+    pub fn memory(&self) -> &Memory {
+        &self.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut Memory {
+        &mut self.memory
+    }
+
     pub fn video(&self) -> &Video {
         &self.video
     }
@@ -84,18 +288,130 @@ impl Emulator {
         self.video.update()
     }
 
+    // Keep the host keys coming in on frames that aren't drawn
+    pub fn poll_input(&mut self) {
+        self.video.poll_input();
+    }
+
     pub fn is_window_open(&self) -> bool {
         self.video.is_open()
     }
 
     pub fn render_display(&mut self) -> Result<(), minifb::Error> {
-        self.video
-            .render(&self.memory, self.memory.rom(), &self.cpu);
+        // Without an NMI generator the TV only locks on while the ROM makes VSYNC
+        let vsyncs = self.io.take_vsync_count();
+        if !self.machine.has_nmi_display() && vsyncs == 0 {
+            self.video.render_no_signal(&self.memory, &self.cpu);
+        } else {
+            self.video
+                .render(&self.memory, self.memory.rom(), &self.cpu);
+        }
         self.video.update()
     }
 
-    pub fn update_keyboard(&mut self) {
+    // In turbo tape mode, whether a loader is busy sampling the playing tape, so
+    // the frontend can skip the frame delay and the display until it's done
+    pub fn tape_turbo_active(&self) -> bool {
+        self.tape_mode() == TapeMode::Turbo && self.deck.is_playing() && self.deck.is_listening()
+    }
+
+    // Record what the machine sends to the MIC socket, e.g. during a ROM SAVE
+    pub fn start_mic_recording(&mut self) {
+        self.io.start_mic_recording();
+    }
+
+    // Everything recorded since start_mic_recording, as a tape
+    pub fn stop_mic_recording(&mut self) -> Option<Tape> {
+        let pulses = self.io.stop_mic_recording(self.cycles)?;
+        Some(Tape::from_recording(pulses, self.clock_hz))
+    }
+
+    // Hand the host keyboard to the machine, returning the actions bound to keys
+    // that went down since the last call
+    pub fn update_keyboard(&mut self) -> Vec<Action> {
         let keys = self.video.get_keys();
-        self.io.update_keys(&keys);
+        let typed = self.video.take_typed();
+        self.io.update_keys(&keys, &typed);
+        self.video
+            .get_keys_pressed()
+            .into_iter()
+            .filter_map(|key| self.io.keymap().action(key))
+            .collect()
+    }
+
+    // Start the CPU again from address 0, as when the power comes on. The ROM
+    // clears the RAM itself; tapes stay in the deck.
+    pub fn reset(&mut self) {
+        self.cpu = Cpu::new();
+        self.deck.stop();
+        self.io.typist_mut().clear();
+    }
+
+    // Write the program in memory to the save directory as the next free
+    // snapshot-N.p, returning where it went
+    pub fn snapshot(&self) -> Result<PathBuf, String> {
+        let data = self
+            .memory
+            .save_program()
+            .ok_or("No program in memory to snapshot")?;
+        let path = (1..)
+            .map(|n| self.save_dir.join(format!("snapshot-{}.p", n)))
+            .find(|path| !path.exists())
+            .expect("ran out of snapshot names");
+        fs::write(&path, &data).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tape::TapeFormat;
+    use crate::tape::fixtures::p_program;
+
+    // An 8K ROM that does nothing but read the keyboard port, as a loader does
+    fn listening_emulator(mode: TapeMode) -> Emulator {
+        let mut rom = vec![0; 0x2000];
+        rom[..4].copy_from_slice(&[0xDB, 0xFE, 0x18, 0xFC]); // IN A,(FE); JR -4
+        let config = MachineConfig::builder(rom)
+            .headless(true)
+            .tape_mode(mode)
+            .build();
+        let mut emulator = Emulator::new(config).unwrap();
+        emulator.load_tape(Tape::from_programs(vec![p_program("T")], TapeFormat::Zx81));
+        emulator
+    }
+
+    fn run_frames(emulator: &mut Emulator, frames: u64) {
+        let end = emulator.cycles + frames * emulator.cycles_per_frame;
+        while emulator.cycles < end {
+            emulator.step();
+        }
+    }
+
+    #[test]
+    fn turbo_runs_only_while_the_deck_plays() {
+        let mut emulator = listening_emulator(TapeMode::Turbo);
+        assert!(!emulator.tape_turbo_active());
+
+        // Auto-play starts the tape once the loader is heard
+        run_frames(&mut emulator, 2);
+        assert!(emulator.deck().is_playing());
+        assert!(emulator.tape_turbo_active());
+
+        emulator.deck_mut().stop();
+        assert!(!emulator.tape_turbo_active());
+        // With auto-play off the loader can't restart it
+        emulator.deck_mut().set_auto_play(false);
+        run_frames(&mut emulator, 2);
+        assert!(!emulator.tape_turbo_active());
+    }
+
+    #[test]
+    fn only_turbo_mode_runs_flat_out() {
+        let mut emulator = listening_emulator(TapeMode::Rom);
+        run_frames(&mut emulator, 2);
+        assert!(emulator.deck().is_playing());
+        assert!(!emulator.tape_turbo_active());
     }
 }
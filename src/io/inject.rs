@@ -0,0 +1,508 @@
+use crate::memory::Memory;
+
+use super::translate::Chord;
+
+// System variables the typist watches
+const FLAGS: u16 = 0x4001; // Bit 2 set for the L cursor, clear for K
+const MODE: u16 = 0x4006; // 0 normally, 1 for the F cursor, 4 for G
+const LAST_K: u16 = 0x4025; // Keys the ROM last saw, 0xFFFF for none (see last_k_code)
+
+// Keyboard scans a key stays down once the ROM has seen it, and up again afterwards.
+// The ROM scans once a frame while it waits for a key (SLOW or FAST) and not at all
+// while it's busy with a line, so counting scans rather than FRAMES waits out the
+// busy spells too.
+const HOLD_SCANS: u32 = 3;
+const RELEASE_SCANS: u32 = 6;
+// Give up waiting for the ROM to see a key after this many scans
+const SEEN_TIMEOUT_SCANS: u32 = 50;
+
+const ENTER: Chord = Chord {
+    shift: false,
+    row: 6,
+    col: 0,
+};
+const FUNCTION: Chord = Chord {
+    shift: true,
+    row: 6,
+    col: 0,
+};
+
+// Keywords the K cursor gives for each letter, A to Z
+const COMMANDS: [&str; 26] = [
+    "NEW", "SCROLL", "CONT", "DIM", "REM", "FOR", "GOTO", "GOSUB", "INPUT", "LOAD", "LIST", "LET",
+    "PAUSE", "NEXT", "POKE", "PRINT", "PLOT", "RUN", "SAVE", "RAND", "IF", "CLS", "UNPLOT",
+    "CLEAR", "RETURN", "COPY",
+];
+
+// Keywords on shifted keys that start a statement, and their keys
+const SHIFTED_COMMANDS: [(&str, char); 5] = [
+    ("STOP", 'A'),
+    ("LPRINT", 'S'),
+    ("SLOW", 'D'),
+    ("FAST", 'F'),
+    ("LLIST", 'G'),
+];
+
+// Tokens on shifted keys used within a statement. Longest first, so <= isn't
+// taken for < then =.
+const SHIFTED_TOKENS: [(&str, char); 9] = [
+    ("THEN", '3'),
+    ("STEP", 'E'),
+    ("AND", '2'),
+    ("OR", 'W'),
+    ("TO", '4'),
+    ("<=", 'R'),
+    (">=", 'Y'),
+    ("<>", 'T'),
+    ("**", 'H'),
+];
+
+// Functions, typed with FUNCTION then their key
+const FUNCTIONS: [(&str, char); 25] = [
+    ("INKEY$", 'B'),
+    ("STR$", 'Y'),
+    ("CHR$", 'U'),
+    ("CODE", 'I'),
+    ("PEEK", 'O'),
+    ("RND", 'T'),
+    ("TAB", 'P'),
+    ("ASN", 'A'),
+    ("ACS", 'S'),
+    ("ATN", 'D'),
+    ("SGN", 'F'),
+    ("ABS", 'G'),
+    ("SIN", 'Q'),
+    ("COS", 'W'),
+    ("TAN", 'E'),
+    ("INT", 'R'),
+    ("SQR", 'H'),
+    ("VAL", 'J'),
+    ("LEN", 'K'),
+    ("USR", 'L'),
+    ("NOT", 'N'),
+    ("EXP", 'X'),
+    ("LN", 'Z'),
+    ("AT", 'C'),
+    ("PI", 'M'),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    Idle,
+    Pressing { seen: bool },
+    Releasing,
+}
+
+// Types text into the ROM's editor by pressing keys for it, one at a time.
+// Keywords go in as single tokens, the way the cursor the ROM shows at the time
+// takes them: commands on the K cursor, shifted tokens and functions (through
+// FUNCTION) on the L cursor. Each key is held until the ROM has seen it and
+// released until it sees nothing, so none are lost or doubled however busy the
+// ROM is.
+pub struct KeyInjector {
+    text: Vec<char>,
+    pos: usize,
+    in_string: bool,
+    keys: Vec<Chord>, // Still to press for the current token, last first
+    current: Option<Chord>,
+    phase: Phase,
+    scans: u32, // Since the phase started
+}
+
+impl Default for KeyInjector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl KeyInjector {
+    pub fn new() -> Self {
+        Self {
+            text: Vec::new(),
+            pos: 0,
+            in_string: false,
+            keys: Vec::new(),
+            current: None,
+            // The ROM's first scans come before it's ready for keys
+            phase: Phase::Releasing,
+            scans: 0,
+        }
+    }
+
+    // Add text to type after whatever is still waiting. Lines end with '\n' (ENTER).
+    pub fn type_text(&mut self, text: &str) {
+        self.text.drain(..self.pos);
+        self.pos = 0;
+        self.text.extend(
+            text.chars()
+                .filter(|&c| c != '\r')
+                .map(|c| c.to_ascii_uppercase()),
+        );
+    }
+
+    pub fn is_typing(&self) -> bool {
+        self.pos < self.text.len() || !self.keys.is_empty() || self.current.is_some()
+    }
+
+    // Forget anything still to type
+    pub fn clear(&mut self) {
+        *self = Self::new();
+    }
+
+    // The keys being held down right now
+    pub fn held(&self) -> Option<Chord> {
+        match self.phase {
+            Phase::Pressing { .. } => self.current,
+            _ => None,
+        }
+    }
+
+    // Move on as the ROM takes the keys. Called each time the ROM's keyboard
+    // routine starts a scan, with LAST_K holding what the last one found.
+    pub fn keyboard_scanned(&mut self, memory: &Memory) {
+        self.scans += 1;
+        let last_k = memory.read_word(LAST_K);
+        match self.phase {
+            Phase::Pressing { seen: false } => {
+                if self
+                    .current
+                    .is_some_and(|chord| last_k == last_k_code(chord))
+                {
+                    self.start(Phase::Pressing { seen: true });
+                } else if self.scans >= SEEN_TIMEOUT_SCANS {
+                    self.start(Phase::Releasing);
+                }
+            }
+            Phase::Pressing { seen: true } => {
+                if self.scans >= HOLD_SCANS {
+                    self.start(Phase::Releasing);
+                }
+            }
+            Phase::Releasing => {
+                if last_k == 0xFFFF && self.scans >= RELEASE_SCANS {
+                    self.current = None;
+                    self.start(Phase::Idle);
+                }
+            }
+            Phase::Idle => {
+                if self.keys.is_empty() {
+                    let l_mode = memory.read(FLAGS) & 0x04 != 0 || memory.read(MODE) != 0;
+                    self.keys = self.next_token(l_mode);
+                    self.keys.reverse();
+                }
+                if let Some(chord) = self.keys.pop() {
+                    self.current = Some(chord);
+                    self.start(Phase::Pressing { seen: false });
+                }
+            }
+        }
+    }
+
+    fn start(&mut self, phase: Phase) {
+        self.phase = phase;
+        self.scans = 0;
+    }
+
+    // The keys for the next thing in the text, given the cursor the ROM is showing
+    fn next_token(&mut self, l_mode: bool) -> Vec<Chord> {
+        while let Some(&c) = self.text.get(self.pos) {
+            if self.in_string {
+                self.pos += 1;
+                if c == '"' {
+                    // "" inside a string is the quote character
+                    if self.text.get(self.pos) == Some(&'"') {
+                        self.pos += 1;
+                        return vec![shifted('Q')];
+                    }
+                    self.in_string = false;
+                } else if c == '\n' {
+                    self.in_string = false;
+                    return vec![ENTER];
+                }
+                match Chord::for_char(c) {
+                    Some(chord) => return vec![chord],
+                    None => continue,
+                }
+            }
+
+            // The ROM puts its own spaces round keywords, and there are none to
+            // type before one
+            if c == ' ' {
+                let next = self.text[self.pos..]
+                    .iter()
+                    .position(|&c| c != ' ')
+                    .map_or(self.text.len(), |skip| self.pos + skip);
+                if !l_mode || next == self.text.len() || self.keyword_at(next, l_mode).is_some() {
+                    self.pos = next;
+                    continue;
+                }
+            }
+
+            if let Some((len, keys)) = self.keyword_at(self.pos, l_mode) {
+                self.pos += len;
+                while self.text.get(self.pos) == Some(&' ') {
+                    self.pos += 1;
+                }
+                return keys;
+            }
+
+            self.pos += 1;
+            if c == '"' {
+                self.in_string = true;
+            }
+            if let Some(chord) = Chord::for_char(c) {
+                return vec![chord];
+            }
+        }
+        Vec::new()
+    }
+
+    // A keyword starting at `pos` that the cursor would take as one token: how many
+    // characters it is, and the keys that type it
+    fn keyword_at(&self, pos: usize, l_mode: bool) -> Option<(usize, Vec<Chord>)> {
+        let starts_word = pos == 0 || !self.text[pos - 1].is_ascii_alphabetic();
+        let matches = |word: &str| {
+            let len = word.chars().count();
+            let text = self.text.get(pos..pos + len)?;
+            if !text.iter().copied().eq(word.chars()) {
+                return None;
+            }
+            // Alphabetic keywords have to be words of their own, not part of a name
+            if word.starts_with(|c: char| c.is_ascii_alphabetic()) {
+                let ends_word = word.ends_with('$')
+                    || !self
+                        .text
+                        .get(pos + len)
+                        .is_some_and(|c| c.is_ascii_alphabetic());
+                if !starts_word || !ends_word {
+                    return None;
+                }
+            }
+            Some(len)
+        };
+
+        if !l_mode {
+            let command = COMMANDS
+                .iter()
+                .zip('A'..='Z')
+                .find_map(|(word, key)| Some((matches(word)?, vec![Chord::for_char(key)?])));
+            return command.or_else(|| {
+                SHIFTED_COMMANDS
+                    .iter()
+                    .find_map(|&(word, key)| Some((matches(word)?, vec![shifted(key)])))
+            });
+        }
+        SHIFTED_TOKENS
+            .iter()
+            .find_map(|&(word, key)| Some((matches(word)?, vec![shifted(key)])))
+            .or_else(|| {
+                FUNCTIONS.iter().find_map(|&(word, key)| {
+                    Some((matches(word)?, vec![FUNCTION, Chord::for_char(key)?]))
+                })
+            })
+    }
+}
+
+// What the ROM's keyboard scan puts in LAST_K for a chord: the row selected in the
+// low byte and the column in the high byte, one bit up to leave bit 0 for SHIFT,
+// all active low
+fn last_k_code(chord: Chord) -> u16 {
+    let row = !(1u8 << chord.row);
+    let mut col = !(2u8 << chord.col);
+    if chord.shift {
+        col &= !1;
+    }
+    u16::from_le_bytes([row, col])
+}
+
+// A letter or digit key with SHIFT
+fn shifted(key: char) -> Chord {
+    let chord = Chord::for_char(key).expect("a key on the matrix");
+    Chord::shifted(chord.row, chord.col)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Enough of the ROM's editor to drive the injector: each scan reports the held
+    // key in LAST_K, and the cursor is K until a line has something besides its
+    // line number
+    struct Editor {
+        memory: Memory,
+        line_number_only: bool,
+        pressed: Vec<Chord>,
+    }
+
+    impl Editor {
+        fn new() -> Self {
+            let mut memory = Memory::new(vec![0; 0x2000], 0x4000);
+            memory.write_word(LAST_K, 0xFFFF);
+            Self {
+                memory,
+                line_number_only: true,
+                pressed: Vec::new(),
+            }
+        }
+
+        fn l_mode(&mut self, l_mode: bool) {
+            let flags = self.memory.read(FLAGS);
+            let flags = if l_mode { flags | 0x04 } else { flags & !0x04 };
+            self.memory.write(FLAGS, flags);
+        }
+
+        // One keyboard scan, taking any key pressed since the last
+        fn scan(&mut self, injector: &mut KeyInjector) {
+            injector.keyboard_scanned(&self.memory);
+            let held = injector.held();
+            let last_k = held.map_or(0xFFFF, last_k_code);
+            if let Some(chord) = held
+                && self.memory.read_word(LAST_K) == 0xFFFF
+            {
+                self.pressed.push(chord);
+                if chord == ENTER {
+                    self.line_number_only = true;
+                } else if chord != FUNCTION && !(chord.row == 3 || chord.row == 4) {
+                    self.line_number_only = false;
+                }
+                self.l_mode(!self.line_number_only);
+            }
+            self.memory.write_word(LAST_K, last_k);
+        }
+
+        fn type_text(text: &str) -> Vec<Chord> {
+            let mut editor = Self::new();
+            let mut injector = KeyInjector::new();
+            injector.type_text(text);
+            for _ in 0..1000 {
+                if !injector.is_typing() {
+                    return editor.pressed;
+                }
+                editor.scan(&mut injector);
+            }
+            panic!("Still typing {:?} after 1000 scans", text);
+        }
+    }
+
+    fn key(c: char) -> Chord {
+        Chord::for_char(c).unwrap()
+    }
+
+    #[test]
+    fn types_a_program_line() {
+        let quote = shifted('P');
+        assert_eq!(
+            Editor::type_text("10 PRINT \"HI\"\n"),
+            [
+                key('1'),
+                key('0'),
+                key('P'), // PRINT on the K cursor
+                quote,
+                key('H'),
+                key('I'),
+                quote,
+                ENTER,
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenises_for_the_cursor() {
+        assert_eq!(
+            Editor::type_text("20 LET C=CODE \"P\" AND A<=B\n"),
+            [
+                key('2'),
+                key('0'),
+                key('L'), // LET on the K cursor
+                key('C'),
+                shifted('L'), // =
+                FUNCTION,
+                key('I'), // CODE
+                shifted('P'),
+                key('P'), // Inside a string, just the letter
+                shifted('P'),
+                shifted('2'), // AND
+                key('A'),
+                shifted('R'), // <=
+                key('B'),
+                ENTER,
+            ]
+        );
+    }
+
+    #[test]
+    fn quotes_inside_strings() {
+        let quote = shifted('P');
+        assert_eq!(
+            Editor::type_text("PRINT \"A\"\"B\"\n"),
+            [
+                key('P'),
+                quote,
+                key('A'),
+                shifted('Q'),
+                key('B'),
+                quote,
+                ENTER
+            ]
+        );
+    }
+
+    #[test]
+    fn holds_a_key_until_the_rom_sees_it() {
+        let mut editor = Editor::new();
+        editor.l_mode(true);
+        let mut injector = KeyInjector::new();
+        injector.type_text("AB");
+        while injector.held().is_none() {
+            injector.keyboard_scanned(&editor.memory);
+        }
+
+        // LAST_K still shows no key, so A stays down
+        for _ in 0..SEEN_TIMEOUT_SCANS - 1 {
+            injector.keyboard_scanned(&editor.memory);
+            assert_eq!(injector.held(), Some(key('A')));
+        }
+
+        // Once the ROM has seen it, it's held a little longer then let go
+        editor.memory.write_word(LAST_K, last_k_code(key('A')));
+        injector.keyboard_scanned(&editor.memory);
+        for _ in 0..HOLD_SCANS - 1 {
+            injector.keyboard_scanned(&editor.memory);
+            assert_eq!(injector.held(), Some(key('A')));
+        }
+        injector.keyboard_scanned(&editor.memory);
+        assert_eq!(injector.held(), None);
+
+        // B waits for the ROM to see A come up
+        for _ in 0..RELEASE_SCANS * 2 {
+            injector.keyboard_scanned(&editor.memory);
+            assert_eq!(injector.held(), None);
+        }
+        editor.memory.write_word(LAST_K, 0xFFFF);
+        injector.keyboard_scanned(&editor.memory);
+        injector.keyboard_scanned(&editor.memory);
+        assert_eq!(injector.held(), Some(key('B')));
+    }
+
+    #[test]
+    fn gives_up_on_a_key_the_rom_never_sees() {
+        let editor = Editor::new();
+        let mut injector = KeyInjector::new();
+        injector.type_text("A");
+        while injector.held().is_none() {
+            injector.keyboard_scanned(&editor.memory);
+        }
+        for _ in 0..SEEN_TIMEOUT_SCANS {
+            injector.keyboard_scanned(&editor.memory);
+        }
+        assert_eq!(injector.held(), None);
+    }
+
+    #[test]
+    fn last_k_codes() {
+        assert_eq!(last_k_code(key('A')), 0xFDFD); // Row 1, column 0
+        assert_eq!(last_k_code(ENTER), 0xFDBF); // Row 6, column 0
+        assert_eq!(last_k_code(shifted('P')), 0xFCDF); // Row 5 with SHIFT
+        assert_eq!(last_k_code(key('B')), 0xDF7F); // Row 7, column 4
+    }
+}
@@ -0,0 +1,314 @@
+// Joystick interfaces. ZX81 games read a joystick one of three ways: as the cursor
+// keys, through a Kempston-style port, or through the ZXpand's joystick port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoystickInterface {
+    Cursor,   // Wired to the keyboard matrix as 5, 6, 7, 8 and 0 for fire
+    Kempston, // Port 0x1F (A5 low), active high: right, left, down, up, fire in bits 0-4
+    Zxpand,   // Port 0x07, active low: up, down, left, right, fire in bits 7-3
+}
+
+impl JoystickInterface {
+    pub fn name(&self) -> &'static str {
+        match self {
+            JoystickInterface::Cursor => "cursor",
+            JoystickInterface::Kempston => "kempston",
+            JoystickInterface::Zxpand => "zxpand",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        [
+            JoystickInterface::Cursor,
+            JoystickInterface::Kempston,
+            JoystickInterface::Zxpand,
+        ]
+        .into_iter()
+        .find(|interface| interface.name().eq_ignore_ascii_case(name))
+    }
+}
+
+// One of the things a joystick can do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoystickInput {
+    Up,
+    Down,
+    Left,
+    Right,
+    Fire,
+}
+
+impl JoystickInput {
+    pub const ALL: [JoystickInput; 5] = [
+        JoystickInput::Up,
+        JoystickInput::Down,
+        JoystickInput::Left,
+        JoystickInput::Right,
+        JoystickInput::Fire,
+    ];
+
+    // Name used in keymap files
+    pub fn name(&self) -> &'static str {
+        match self {
+            JoystickInput::Up => "joy-up",
+            JoystickInput::Down => "joy-down",
+            JoystickInput::Left => "joy-left",
+            JoystickInput::Right => "joy-right",
+            JoystickInput::Fire => "joy-fire",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|input| input.name().eq_ignore_ascii_case(name))
+    }
+
+    // Where a cursor joystick presses on the keyboard matrix
+    fn cursor_key(&self) -> (usize, usize) {
+        match self {
+            JoystickInput::Left => (3, 4),  // 5
+            JoystickInput::Down => (4, 4),  // 6
+            JoystickInput::Up => (4, 3),    // 7
+            JoystickInput::Right => (4, 2), // 8
+            JoystickInput::Fire => (4, 0),  // 0
+        }
+    }
+
+    fn kempston_bit(&self) -> u8 {
+        match self {
+            JoystickInput::Right => 0x01,
+            JoystickInput::Left => 0x02,
+            JoystickInput::Down => 0x04,
+            JoystickInput::Up => 0x08,
+            JoystickInput::Fire => 0x10,
+        }
+    }
+
+    fn zxpand_bit(&self) -> u8 {
+        match self {
+            JoystickInput::Up => 0x80,
+            JoystickInput::Down => 0x40,
+            JoystickInput::Left => 0x20,
+            JoystickInput::Right => 0x10,
+            JoystickInput::Fire => 0x08,
+        }
+    }
+}
+
+// Which way the stick is pushed and whether fire is down
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct JoystickState {
+    pub up: bool,
+    pub down: bool,
+    pub left: bool,
+    pub right: bool,
+    pub fire: bool,
+}
+
+impl JoystickState {
+    pub fn is_pressed(&self, input: JoystickInput) -> bool {
+        match input {
+            JoystickInput::Up => self.up,
+            JoystickInput::Down => self.down,
+            JoystickInput::Left => self.left,
+            JoystickInput::Right => self.right,
+            JoystickInput::Fire => self.fire,
+        }
+    }
+
+    pub fn set(&mut self, input: JoystickInput, pressed: bool) {
+        match input {
+            JoystickInput::Up => self.up = pressed,
+            JoystickInput::Down => self.down = pressed,
+            JoystickInput::Left => self.left = pressed,
+            JoystickInput::Right => self.right = pressed,
+            JoystickInput::Fire => self.fire = pressed,
+        }
+    }
+
+    // Everything pressed in either
+    pub fn union(&self, other: &JoystickState) -> JoystickState {
+        JoystickState {
+            up: self.up || other.up,
+            down: self.down || other.down,
+            left: self.left || other.left,
+            right: self.right || other.right,
+            fire: self.fire || other.fire,
+        }
+    }
+
+    fn pressed(&self) -> impl Iterator<Item = JoystickInput> + '_ {
+        JoystickInput::ALL
+            .into_iter()
+            .filter(|&input| self.is_pressed(input))
+    }
+}
+
+// A joystick plugged into one of the interfaces. Host keys and programs driving
+// the emulator each hold it their own way, and the interface sees both.
+pub struct Joystick {
+    interface: JoystickInterface,
+    state: JoystickState, // Set through the API, e.g. from a gamepad or a test
+    host_keys: JoystickState, // Held on the host keyboard this frame
+}
+
+impl Joystick {
+    pub fn new(interface: JoystickInterface) -> Self {
+        Self {
+            interface,
+            state: JoystickState::default(),
+            host_keys: JoystickState::default(),
+        }
+    }
+
+    pub fn interface(&self) -> JoystickInterface {
+        self.interface
+    }
+
+    pub fn state(&self) -> JoystickState {
+        self.state
+    }
+
+    pub fn set_state(&mut self, state: JoystickState) {
+        self.state = state;
+    }
+
+    pub fn set_host_keys(&mut self, host_keys: JoystickState) {
+        self.host_keys = host_keys;
+    }
+
+    fn effective(&self) -> JoystickState {
+        self.state.union(&self.host_keys)
+    }
+
+    // What the interface puts on the bus for an IN from `port`, if it answers
+    pub fn read_port(&self, port: u8) -> Option<u8> {
+        let state = self.effective();
+        match self.interface {
+            // Decodes A5 only; the ULA has the even ports, so A0 is high
+            JoystickInterface::Kempston if port & 0x21 == 0x01 => Some(
+                state
+                    .pressed()
+                    .fold(0, |bits, input| bits | input.kempston_bit()),
+            ),
+            JoystickInterface::Zxpand if port == 0x07 => Some(
+                state
+                    .pressed()
+                    .fold(0xFF, |bits, input| bits & !input.zxpand_bit()),
+            ),
+            _ => None,
+        }
+    }
+
+    // A cursor joystick holds keys down on the matrix
+    pub fn press_keys(&self, keyboard_state: &mut [[bool; 5]; 8]) {
+        if self.interface != JoystickInterface::Cursor {
+            return;
+        }
+        for input in self.effective().pressed() {
+            let (row, col) = input.cursor_key();
+            keyboard_state[row][col] = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pushed(interface: JoystickInterface, input: JoystickInput) -> Joystick {
+        let mut joystick = Joystick::new(interface);
+        let mut state = JoystickState::default();
+        state.set(input, true);
+        joystick.set_state(state);
+        joystick
+    }
+
+    #[test]
+    fn kempston_is_active_high() {
+        for (input, value) in [
+            (JoystickInput::Right, 0x01),
+            (JoystickInput::Left, 0x02),
+            (JoystickInput::Down, 0x04),
+            (JoystickInput::Up, 0x08),
+            (JoystickInput::Fire, 0x10),
+        ] {
+            let joystick = pushed(JoystickInterface::Kempston, input);
+            assert_eq!(joystick.read_port(0x1F), Some(value), "{:?}", input);
+        }
+        let idle = Joystick::new(JoystickInterface::Kempston);
+        assert_eq!(idle.read_port(0x1F), Some(0x00));
+    }
+
+    #[test]
+    fn kempston_answers_odd_ports_with_a5_low() {
+        let joystick = pushed(JoystickInterface::Kempston, JoystickInput::Fire);
+        for port in [0x01, 0x1F, 0x5F, 0xDF] {
+            assert_eq!(joystick.read_port(port), Some(0x10), "port {:02X}", port);
+        }
+        for port in [0x1E, 0xFE, 0x3F, 0x21, 0xFF] {
+            assert_eq!(joystick.read_port(port), None, "port {:02X}", port);
+        }
+    }
+
+    #[test]
+    fn zxpand_is_active_low() {
+        for (input, value) in [
+            (JoystickInput::Up, 0x7F),
+            (JoystickInput::Down, 0xBF),
+            (JoystickInput::Left, 0xDF),
+            (JoystickInput::Right, 0xEF),
+            (JoystickInput::Fire, 0xF7),
+        ] {
+            let joystick = pushed(JoystickInterface::Zxpand, input);
+            assert_eq!(joystick.read_port(0x07), Some(value), "{:?}", input);
+        }
+        let idle = Joystick::new(JoystickInterface::Zxpand);
+        assert_eq!(idle.read_port(0x07), Some(0xFF));
+        assert_eq!(idle.read_port(0x1F), None);
+        assert_eq!(idle.read_port(0x06), None);
+    }
+
+    #[test]
+    fn host_keys_and_state_combine() {
+        let mut joystick = pushed(JoystickInterface::Kempston, JoystickInput::Up);
+        joystick.set_host_keys(JoystickState {
+            fire: true,
+            ..Default::default()
+        });
+        assert_eq!(joystick.read_port(0x1F), Some(0x18));
+
+        let mut joystick = pushed(JoystickInterface::Zxpand, JoystickInput::Left);
+        joystick.set_host_keys(JoystickState {
+            right: true,
+            ..Default::default()
+        });
+        assert_eq!(joystick.read_port(0x07), Some(0xCF));
+    }
+
+    #[test]
+    fn cursor_presses_keys() {
+        for (input, key) in [
+            (JoystickInput::Left, (3, 4)),
+            (JoystickInput::Down, (4, 4)),
+            (JoystickInput::Up, (4, 3)),
+            (JoystickInput::Right, (4, 2)),
+            (JoystickInput::Fire, (4, 0)),
+        ] {
+            let joystick = pushed(JoystickInterface::Cursor, input);
+            let mut keyboard_state = [[false; 5]; 8];
+            joystick.press_keys(&mut keyboard_state);
+            let mut expected = [[false; 5]; 8];
+            expected[key.0][key.1] = true;
+            assert_eq!(keyboard_state, expected, "{:?}", input);
+            assert_eq!(joystick.read_port(0x1F), None);
+            assert_eq!(joystick.read_port(0x07), None);
+        }
+
+        // The other interfaces leave the keyboard alone
+        let joystick = pushed(JoystickInterface::Kempston, JoystickInput::Fire);
+        let mut keyboard_state = [[false; 5]; 8];
+        joystick.press_keys(&mut keyboard_state);
+        assert_eq!(keyboard_state, [[false; 5]; 8]);
+    }
+}
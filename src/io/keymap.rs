@@ -0,0 +1,412 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use minifb::Key;
+
+use super::joystick::JoystickInput;
+use super::layout::KeyboardLayout;
+use super::translate::{Chord, UNSHIFTED};
+
+// Things a host key can do besides pressing ZX81 keys
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    TapePlay,
+    TapeStop,
+    TapeRewind,
+    TapeNext,
+    TapePrevious,
+    TapeEject,
+    Snapshot, // Write the program in memory out as a .p file
+    Reset,
+    Pause,
+}
+
+impl Action {
+    const ALL: [Action; 9] = [
+        Action::TapePlay,
+        Action::TapeStop,
+        Action::TapeRewind,
+        Action::TapeNext,
+        Action::TapePrevious,
+        Action::TapeEject,
+        Action::Snapshot,
+        Action::Reset,
+        Action::Pause,
+    ];
+
+    // Name used in keymap files
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::TapePlay => "tape-play",
+            Action::TapeStop => "tape-stop",
+            Action::TapeRewind => "tape-rewind",
+            Action::TapeNext => "tape-next",
+            Action::TapePrevious => "tape-previous",
+            Action::TapeEject => "tape-eject",
+            Action::Snapshot => "snapshot",
+            Action::Reset => "reset",
+            Action::Pause => "pause",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|action| action.name().eq_ignore_ascii_case(name))
+    }
+}
+
+// What a host key is bound to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Binding {
+    Matrix(Vec<(usize, usize)>), // Every ZX81 key it holds down, e.g. SHIFT and P
+    Action(Action),
+    Joystick(JoystickInput), // Only does anything with a joystick interface fitted
+}
+
+// Which host key does what. Starts out as the machine's layout plus the function
+// keys, and a keymap file can rebind any key over the top:
+//
+//   # Host key = ZX81 keys joined with +, an action, or none
+//   Semicolon = SHIFT+X
+//   Backspace = SHIFT+0
+//   F12 = reset
+//   RightCtrl = joy-fire
+//   Pause = none
+#[derive(Debug, Clone)]
+pub struct Keymap {
+    bindings: HashMap<Key, Binding>,
+}
+
+impl Keymap {
+    pub fn new(layout: &KeyboardLayout) -> Self {
+        let mut bindings = HashMap::new();
+        for (row, keys) in layout.iter().enumerate() {
+            for (col, &key) in keys.iter().enumerate() {
+                bindings.insert(key, Binding::Matrix(vec![(row, col)]));
+            }
+        }
+        // Either shift key works as SHIFT
+        bindings.insert(Key::RightShift, Binding::Matrix(vec![Chord::SHIFT]));
+
+        for (key, action) in [
+            (Key::F5, Action::TapePlay),
+            (Key::F6, Action::TapeStop),
+            (Key::F7, Action::TapeRewind),
+            (Key::F8, Action::TapeNext),
+            (Key::F9, Action::TapeEject),
+            (Key::F11, Action::Snapshot),
+            (Key::F12, Action::Reset),
+            (Key::Pause, Action::Pause),
+        ] {
+            bindings.insert(key, Binding::Action(action));
+        }
+
+        // The numeric keypad is the joystick
+        for (key, input) in [
+            (Key::NumPad8, JoystickInput::Up),
+            (Key::NumPad2, JoystickInput::Down),
+            (Key::NumPad4, JoystickInput::Left),
+            (Key::NumPad6, JoystickInput::Right),
+            (Key::NumPad0, JoystickInput::Fire),
+        ] {
+            bindings.insert(key, Binding::Joystick(input));
+        }
+        Self { bindings }
+    }
+
+    // Bind a host key, or leave it doing nothing with None
+    pub fn bind(&mut self, key: Key, binding: Option<Binding>) {
+        match binding {
+            Some(binding) => self.bindings.insert(key, binding),
+            None => self.bindings.remove(&key),
+        };
+    }
+
+    // The ZX81 keys a host key holds down
+    pub fn matrix(&self, key: Key) -> Option<&[(usize, usize)]> {
+        match self.bindings.get(&key)? {
+            Binding::Matrix(positions) => Some(positions),
+            _ => None,
+        }
+    }
+
+    pub fn action(&self, key: Key) -> Option<Action> {
+        match self.bindings.get(&key)? {
+            Binding::Action(action) => Some(*action),
+            _ => None,
+        }
+    }
+
+    // The joystick input a host key works
+    pub fn joystick(&self, key: Key) -> Option<JoystickInput> {
+        match self.bindings.get(&key)? {
+            Binding::Joystick(input) => Some(*input),
+            _ => None,
+        }
+    }
+
+    // Apply the bindings in a keymap file
+    pub fn load_file(&mut self, path: impl AsRef<Path>) -> Result<(), String> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        self.load(&text)
+            .map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    // Apply bindings given as the lines of a keymap file. Nothing changes unless
+    // every line makes sense.
+    pub fn load(&mut self, text: &str) -> Result<(), String> {
+        let mut bindings = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let binding = parse_line(line).map_err(|e| format!("line {}: {}", number + 1, e))?;
+            bindings.push(binding);
+        }
+        for (key, binding) in bindings {
+            self.bind(key, binding);
+        }
+        Ok(())
+    }
+}
+
+fn parse_line(line: &str) -> Result<(Key, Option<Binding>), String> {
+    let (key, binding) = line
+        .split_once('=')
+        .ok_or_else(|| format!("Expected <host key> = <binding>, got \"{}\"", line))?;
+    let (key, binding) = (key.trim(), binding.trim());
+    let key = host_key(key).ok_or_else(|| format!("Unknown host key: {}", key))?;
+
+    if binding.eq_ignore_ascii_case("none") {
+        return Ok((key, None));
+    }
+    if let Some(action) = Action::from_name(binding) {
+        return Ok((key, Some(Binding::Action(action))));
+    }
+    if let Some(input) = JoystickInput::from_name(binding) {
+        return Ok((key, Some(Binding::Joystick(input))));
+    }
+    let positions = binding
+        .split('+')
+        .map(|name| {
+            let name = name.trim();
+            matrix_key(name).ok_or_else(|| format!("Unknown ZX81 key or action: {}", name))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok((key, Some(Binding::Matrix(positions))))
+}
+
+// A ZX81 key by the legend on it
+fn matrix_key(name: &str) -> Option<(usize, usize)> {
+    let legend = match name.to_ascii_uppercase().as_str() {
+        "SHIFT" => return Some(Chord::SHIFT),
+        "ENTER" | "NEWLINE" => '\n',
+        "SPACE" => ' ',
+        "PERIOD" | "." => '.',
+        name if name.len() == 1 => name.chars().next()?,
+        _ => return None,
+    };
+    UNSHIFTED.iter().enumerate().find_map(|(row, keys)| {
+        let col = keys.iter().position(|&k| k == legend)?;
+        Some((row, col))
+    })
+}
+
+// A host key by its name in minifb, e.g. "A", "Key1", "Semicolon", "F5"
+fn host_key(name: &str) -> Option<Key> {
+    HOST_KEYS
+        .into_iter()
+        .find(|key| format!("{:?}", key).eq_ignore_ascii_case(name))
+}
+
+const HOST_KEYS: [Key; 106] = [
+    Key::Key0,
+    Key::Key1,
+    Key::Key2,
+    Key::Key3,
+    Key::Key4,
+    Key::Key5,
+    Key::Key6,
+    Key::Key7,
+    Key::Key8,
+    Key::Key9,
+    Key::A,
+    Key::B,
+    Key::C,
+    Key::D,
+    Key::E,
+    Key::F,
+    Key::G,
+    Key::H,
+    Key::I,
+    Key::J,
+    Key::K,
+    Key::L,
+    Key::M,
+    Key::N,
+    Key::O,
+    Key::P,
+    Key::Q,
+    Key::R,
+    Key::S,
+    Key::T,
+    Key::U,
+    Key::V,
+    Key::W,
+    Key::X,
+    Key::Y,
+    Key::Z,
+    Key::F1,
+    Key::F2,
+    Key::F3,
+    Key::F4,
+    Key::F5,
+    Key::F6,
+    Key::F7,
+    Key::F8,
+    Key::F9,
+    Key::F10,
+    Key::F11,
+    Key::F12,
+    Key::F13,
+    Key::F14,
+    Key::F15,
+    Key::Down,
+    Key::Left,
+    Key::Right,
+    Key::Up,
+    Key::Apostrophe,
+    Key::Backquote,
+    Key::Backslash,
+    Key::Comma,
+    Key::Equal,
+    Key::LeftBracket,
+    Key::Minus,
+    Key::Period,
+    Key::RightBracket,
+    Key::Semicolon,
+    Key::Slash,
+    Key::Backspace,
+    Key::Delete,
+    Key::End,
+    Key::Enter,
+    Key::Escape,
+    Key::Home,
+    Key::Insert,
+    Key::Menu,
+    Key::PageDown,
+    Key::PageUp,
+    Key::Pause,
+    Key::Space,
+    Key::Tab,
+    Key::NumLock,
+    Key::CapsLock,
+    Key::ScrollLock,
+    Key::LeftShift,
+    Key::RightShift,
+    Key::LeftCtrl,
+    Key::RightCtrl,
+    Key::NumPad0,
+    Key::NumPad1,
+    Key::NumPad2,
+    Key::NumPad3,
+    Key::NumPad4,
+    Key::NumPad5,
+    Key::NumPad6,
+    Key::NumPad7,
+    Key::NumPad8,
+    Key::NumPad9,
+    Key::NumPadDot,
+    Key::NumPadSlash,
+    Key::NumPadAsterisk,
+    Key::NumPadMinus,
+    Key::NumPadPlus,
+    Key::NumPadEnter,
+    Key::LeftAlt,
+    Key::RightAlt,
+    Key::LeftSuper,
+    Key::RightSuper,
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::layout::SINCLAIR_LAYOUT;
+
+    fn keymap() -> Keymap {
+        Keymap::new(&SINCLAIR_LAYOUT)
+    }
+
+    #[test]
+    fn binds_zx81_keys() {
+        let mut keymap = keymap();
+        keymap
+            .load("Semicolon = SHIFT+X\nBackspace = shift + 0\nTab = enter\nKey1 = .")
+            .unwrap();
+        assert_eq!(keymap.matrix(Key::Semicolon), Some(&[(0, 0), (0, 2)][..]));
+        assert_eq!(keymap.matrix(Key::Backspace), Some(&[(0, 0), (4, 0)][..]));
+        assert_eq!(keymap.matrix(Key::Tab), Some(&[(6, 0)][..]));
+        assert_eq!(keymap.matrix(Key::Key1), Some(&[(7, 1)][..]));
+        assert_eq!(keymap.action(Key::Semicolon), None);
+    }
+
+    #[test]
+    fn binds_actions_and_joystick() {
+        let mut keymap = keymap();
+        keymap
+            .load("F1 = tape-play\nA = Reset\nRightCtrl = joy-fire\nUp = JOY-UP")
+            .unwrap();
+        assert_eq!(keymap.action(Key::F1), Some(Action::TapePlay));
+        assert_eq!(keymap.action(Key::A), Some(Action::Reset));
+        assert_eq!(keymap.matrix(Key::A), None);
+        assert_eq!(keymap.joystick(Key::RightCtrl), Some(JoystickInput::Fire));
+        assert_eq!(keymap.joystick(Key::Up), Some(JoystickInput::Up));
+    }
+
+    #[test]
+    fn none_unbinds() {
+        let mut keymap = keymap();
+        assert_eq!(keymap.action(Key::F12), Some(Action::Reset));
+        keymap.load("F12 = none\nQ = NONE").unwrap();
+        assert_eq!(keymap.action(Key::F12), None);
+        assert_eq!(keymap.matrix(Key::Q), None);
+    }
+
+    #[test]
+    fn skips_comments_and_blank_lines() {
+        let mut keymap = keymap();
+        keymap
+            .load("# Comment = reset\n\n   \nF1 = reset # F1 = tape-play\n  # indented\n")
+            .unwrap();
+        assert_eq!(keymap.action(Key::F1), Some(Action::Reset));
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        let cases = [
+            (
+                "F1 = reset\nF2",
+                "line 2: Expected <host key> = <binding>, got \"F2\"",
+            ),
+            ("# ok\nNotAKey = A", "line 2: Unknown host key: NotAKey"),
+            ("A = SHIFT+Ω", "line 1: Unknown ZX81 key or action: Ω"),
+            ("A = jump", "line 1: Unknown ZX81 key or action: jump"),
+            ("A = SHIFT+", "line 1: Unknown ZX81 key or action: "),
+        ];
+        for (text, error) in cases {
+            assert_eq!(keymap().load(text), Err(error.to_string()), "{}", text);
+        }
+    }
+
+    #[test]
+    fn bad_line_changes_nothing() {
+        let mut keymap = keymap();
+        assert!(keymap.load("F12 = none\nA = B\nB = nonsense").is_err());
+        assert_eq!(keymap.action(Key::F12), Some(Action::Reset));
+        assert_eq!(keymap.matrix(Key::A), Some(&[(1, 0)][..]));
+    }
+}
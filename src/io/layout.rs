@@ -0,0 +1,32 @@
+use minifb::Key;
+
+// Host keys for each position of the 8×5 keyboard matrix
+// Row order follows the address line that selects it (A8 first), columns are D0-D4
+pub type KeyboardLayout = [[Key; 5]; 8];
+
+pub const SINCLAIR_LAYOUT: KeyboardLayout = [
+    [Key::LeftShift, Key::Z, Key::X, Key::C, Key::V],
+    [Key::A, Key::S, Key::D, Key::F, Key::G],
+    [Key::Q, Key::W, Key::E, Key::R, Key::T],
+    [Key::Key1, Key::Key2, Key::Key3, Key::Key4, Key::Key5],
+    [Key::Key0, Key::Key9, Key::Key8, Key::Key7, Key::Key6],
+    [Key::P, Key::O, Key::I, Key::U, Key::Y],
+    [Key::Enter, Key::L, Key::K, Key::J, Key::H],
+    [Key::Space, Key::Period, Key::M, Key::N, Key::B],
+];
+
+pub fn find_key(layout: &KeyboardLayout, key: Key) -> Option<(usize, usize)> {
+    // Either shift key works as SHIFT
+    let key = if key == Key::RightShift {
+        Key::LeftShift
+    } else {
+        key
+    };
+
+    for (row, keys) in layout.iter().enumerate() {
+        if let Some(col) = keys.iter().position(|&k| k == key) {
+            return Some((row, col));
+        }
+    }
+    None
+}
@@ -1,121 +1,293 @@
-use crate::tape::Tape;
+use crate::machine::{Machine, MachineConfig, TvStandard};
+use crate::tape::{MicRecorder, TapeDeck};
+
+mod inject;
+mod joystick;
+mod keymap;
+mod layout;
+mod translate;
+pub use inject::KeyInjector;
+pub use joystick::{Joystick, JoystickInput, JoystickInterface, JoystickState};
+pub use keymap::{Action, Binding, Keymap};
+pub use layout::{KeyboardLayout, SINCLAIR_LAYOUT, find_key};
+pub use translate::{Chord, KeyTranslator};
 
 pub struct IoController {
     keyboard_state: [[bool; 5]; 8],
+    keymap: Keymap,
+    translator: Option<KeyTranslator>, // Host symbols and editing keys as SHIFT chords
+    typist: KeyInjector,               // Text being typed in by pressing keys for it
+    joystick: Option<Joystick>,
+    fifty_hz: bool, // Position of the 50/60 Hz jumper on the board
+    // MIC/VSYNC line: reading a port with A0 low pulls it low, any OUT releases it
+    mic_level: bool,
+    vsync_count: u32, // VSYNC pulses started since last asked
+    ear_reads: u32,   // Reads of the EAR/keyboard port since last asked
+    mic_recorder: Option<MicRecorder>,
+    t_state: u64, // Time of the instruction currently executing
+}
+
+impl Default for IoController {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl IoController {
     pub fn new() -> Self {
         Self {
             keyboard_state: [[false; 5]; 8],
+            keymap: Keymap::new(Machine::Zx81.keyboard_layout()),
+            translator: None,
+            typist: KeyInjector::new(),
+            joystick: None,
+            fifty_hz: true,
+            mic_level: true,
+            vsync_count: 0,
+            ear_reads: 0,
+            mic_recorder: None,
+            t_state: 0,
         }
     }
 
-    pub fn read_port(&self, port: u8, addr_high: u8, tape: &Option<Tape>) -> u8 {
-        if port == 0xFE {
-            let mut result = 0xBF;
-
-            let row = match addr_high {
-                0xFE => 0,
-                0xFD => 1,
-                0xFB => 2,
-                0xF7 => 3,
-                0xEF => 4,
-                0xDF => 5,
-                0xBF => 6,
-                0x7F => 7,
-                _ => return 0xBF,
-            };
-
-            for col in 0..5 {
-                if self.keyboard_state[row][col] {
+    pub fn from_config(config: &MachineConfig) -> Self {
+        Self {
+            keymap: config.keymap.clone(),
+            translator: config.frontend.smart_keys.then(KeyTranslator::new),
+            joystick: config.joystick.map(Joystick::new),
+            fifty_hz: config.tv_standard == TvStandard::Pal,
+            ..Self::new()
+        }
+    }
+
+    pub fn set_t_state(&mut self, t_state: u64) {
+        self.t_state = t_state;
+    }
+
+    pub fn mic_level(&self) -> bool {
+        self.mic_level
+    }
+
+    // Capture MIC changes from now on, as a tape would hear them
+    pub fn start_mic_recording(&mut self) {
+        self.mic_recorder = Some(MicRecorder::new(self.t_state, !self.mic_level));
+    }
+
+    // The captured pulses, if a recording was running
+    pub fn stop_mic_recording(&mut self, now: u64) -> Option<Vec<(bool, u64)>> {
+        self.mic_recorder
+            .take()
+            .map(|recorder| recorder.finish(now))
+    }
+
+    // How many VSYNC pulses the software has generated since the last call
+    pub fn take_vsync_count(&mut self) -> u32 {
+        std::mem::take(&mut self.vsync_count)
+    }
+
+    // How many times the EAR bit has been read since the last call
+    pub fn take_ear_reads(&mut self) -> u32 {
+        std::mem::take(&mut self.ear_reads)
+    }
+
+    fn set_mic_level(&mut self, level: bool) {
+        if self.mic_level && !level {
+            self.vsync_count += 1;
+        }
+        self.mic_level = level;
+        // The tape signal is the VSYNC pulse, so it's high while MIC is held low
+        if let Some(recorder) = &mut self.mic_recorder {
+            recorder.set_level(self.t_state, !level);
+        }
+    }
+
+    // The ULA only decodes A0, so any even port reads the keyboard. Each row whose
+    // bit in the high byte is low is selected, and a key held in any of them pulls
+    // its column low (a high byte of 0x00 reads "any key"). Odd ports are left to
+    // a joystick interface, if one is fitted.
+    pub fn read_port(&mut self, port: u8, addr_high: u8, deck: &TapeDeck) -> u8 {
+        if port & 0x01 != 0 {
+            return self
+                .joystick
+                .as_ref()
+                .and_then(|joystick| joystick.read_port(port))
+                .unwrap_or(0xFF);
+        }
+        // Start of VSYNC, drives MIC low
+        self.set_mic_level(false);
+        self.ear_reads += 1;
+
+        let mut keyboard_state = self.keyboard_state;
+        if let Some(chord) = self.typist.held() {
+            chord.press(&mut keyboard_state);
+        }
+        if let Some(joystick) = &self.joystick {
+            joystick.press_keys(&mut keyboard_state);
+        }
+
+        // Bit 5 isn't connected and floats high
+        let mut result = 0x3F;
+        for (row, keys) in keyboard_state.iter().enumerate() {
+            if addr_high & (1 << row) != 0 {
+                continue;
+            }
+            for (col, &pressed) in keys.iter().enumerate() {
+                if pressed {
                     result &= !(1 << col);
                 }
             }
+        }
 
-            // EAR bit (bit 7) from tape
-            // On real ZX81, bit 7 is HIGH (0x80) when tape signal is present
-            // and LOW (0x00) during silence
-            let ear_bit = if let Some(t) = tape {
-                if t.is_playing() {
-                    if t.get_level() { 0x80 } else { 0x00 }
-                } else {
-                    0x00 // Low when not playing
-                }
-            } else {
-                0x00 // Low when no tape loaded
-            };
-
-            result = (result & 0x7F) | ear_bit; // Set bit 7 based on tape signal
-            result
-        } else {
-            0xBF
+        // Bit 6 is the 50/60 Hz jumper, which the ROM reads at start up to set the
+        // number of blank lines (MARGIN)
+        if self.fifty_hz {
+            result |= 0x40;
         }
+
+        // EAR bit (bit 7) from tape: high while the tape signal is, low during
+        // silence and when the deck is stopped or empty. It's there whatever the
+        // high byte, the ROM's loader samples it with a loop counter on the bus.
+        if deck.ear_level() {
+            result |= 0x80;
+        }
+        result
     }
 
-    pub fn update_keys(&mut self, keys: &[minifb::Key]) {
+    // Host keys held down, and the characters they typed since the last frame (only
+    // looked at when translating host symbols)
+    pub fn update_keys(&mut self, keys: &[minifb::Key], typed: &[char]) {
         self.keyboard_state = [[false; 5]; 8];
 
-        for key in keys {
-            if let Some((row, col)) = Self::map_key(*key) {
+        if let Some(joystick) = &mut self.joystick {
+            let mut host_keys = JoystickState::default();
+            for input in keys.iter().filter_map(|&key| self.keymap.joystick(key)) {
+                host_keys.set(input, true);
+            }
+            joystick.set_host_keys(host_keys);
+        }
+
+        if let Some(translator) = &mut self.translator {
+            translator.update(&self.keymap, keys, typed, &mut self.keyboard_state);
+            return;
+        }
+        for &key in keys {
+            for &(row, col) in self.keymap.matrix(key).unwrap_or_default() {
                 self.keyboard_state[row][col] = true;
             }
         }
     }
 
-    fn map_key(key: minifb::Key) -> Option<(usize, usize)> {
-        match key {
-            minifb::Key::LeftShift | minifb::Key::RightShift => Some((0, 0)),
-            minifb::Key::Z => Some((0, 1)),
-            minifb::Key::X => Some((0, 2)),
-            minifb::Key::C => Some((0, 3)),
-            minifb::Key::V => Some((0, 4)),
-
-            minifb::Key::A => Some((1, 0)),
-            minifb::Key::S => Some((1, 1)),
-            minifb::Key::D => Some((1, 2)),
-            minifb::Key::F => Some((1, 3)),
-            minifb::Key::G => Some((1, 4)),
-
-            minifb::Key::Q => Some((2, 0)),
-            minifb::Key::W => Some((2, 1)),
-            minifb::Key::E => Some((2, 2)),
-            minifb::Key::R => Some((2, 3)),
-            minifb::Key::T => Some((2, 4)),
-
-            minifb::Key::Key1 => Some((3, 0)),
-            minifb::Key::Key2 => Some((3, 1)),
-            minifb::Key::Key3 => Some((3, 2)),
-            minifb::Key::Key4 => Some((3, 3)),
-            minifb::Key::Key5 => Some((3, 4)),
-
-            minifb::Key::Key0 => Some((4, 0)),
-            minifb::Key::Key9 => Some((4, 1)),
-            minifb::Key::Key8 => Some((4, 2)),
-            minifb::Key::Key7 => Some((4, 3)),
-            minifb::Key::Key6 => Some((4, 4)),
-
-            minifb::Key::P => Some((5, 0)),
-            minifb::Key::O => Some((5, 1)),
-            minifb::Key::I => Some((5, 2)),
-            minifb::Key::U => Some((5, 3)),
-            minifb::Key::Y => Some((5, 4)),
-
-            minifb::Key::Enter => Some((6, 0)),
-            minifb::Key::L => Some((6, 1)),
-            minifb::Key::K => Some((6, 2)),
-            minifb::Key::J => Some((6, 3)),
-            minifb::Key::H => Some((6, 4)),
-
-            minifb::Key::Space => Some((7, 0)),
-            minifb::Key::Period => Some((7, 1)),
-            minifb::Key::M => Some((7, 2)),
-            minifb::Key::N => Some((7, 3)),
-            minifb::Key::B => Some((7, 4)),
-
-            _ => None,
+    pub fn typist(&self) -> &KeyInjector {
+        &self.typist
+    }
+
+    pub fn typist_mut(&mut self) -> &mut KeyInjector {
+        &mut self.typist
+    }
+
+    // The joystick, if an interface is fitted
+    pub fn joystick(&self) -> Option<&Joystick> {
+        self.joystick.as_ref()
+    }
+
+    pub fn joystick_mut(&mut self) -> Option<&mut Joystick> {
+        self.joystick.as_mut()
+    }
+
+    pub fn keymap(&self) -> &Keymap {
+        &self.keymap
+    }
+
+    pub fn write_port(&mut self, _port: u8, _value: u8) {
+        // Any OUT ends VSYNC and lets MIC go high again
+        self.set_mic_level(true);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::machine::CLOCK_HZ;
+    use crate::tape::fixtures::p_program;
+    use crate::tape::{Tape, TapeFormat};
+
+    // Bits 0-4 hold the key columns
+    const KEYS: u8 = 0x1F;
+
+    #[test]
+    fn any_even_port_reads_the_keyboard() {
+        let mut io = IoController::new();
+        io.keyboard_state[0][1] = true; // Z
+        let deck = TapeDeck::new();
+        for port in [0xFE, 0x00, 0x7E, 0x12] {
+            assert_eq!(io.read_port(port, 0xFE, &deck) & KEYS, 0x1D);
+        }
+        assert_eq!(io.take_ear_reads(), 4);
+    }
+
+    #[test]
+    fn selected_rows_are_anded() {
+        let mut io = IoController::new();
+        io.keyboard_state[0][1] = true;
+        io.keyboard_state[3][4] = true;
+        io.keyboard_state[7][0] = true;
+        let deck = TapeDeck::new();
+
+        assert_eq!(io.read_port(0xFE, 0xFE, &deck) & KEYS, 0x1D);
+        assert_eq!(io.read_port(0xFE, 0xF7, &deck) & KEYS, 0x0F);
+        assert_eq!(io.read_port(0xFE, 0xF6, &deck) & KEYS, 0x0D);
+        assert_eq!(io.read_port(0xFE, 0x00, &deck) & KEYS, 0x0C);
+        // No row selected, nothing pressed
+        assert_eq!(io.read_port(0xFE, 0xFF, &deck) & KEYS, KEYS);
+    }
+
+    #[test]
+    fn bit_5_floats_high_and_bit_6_is_the_jumper() {
+        let deck = TapeDeck::new();
+        let mut io = IoController::new();
+        assert_eq!(io.read_port(0xFE, 0x00, &deck), 0x7F);
+
+        io.fifty_hz = false;
+        io.keyboard_state[2][0] = true;
+        assert_eq!(io.read_port(0xFE, 0x00, &deck), 0x3E);
+    }
+
+    #[test]
+    fn bit_7_carries_ear() {
+        let mut io = IoController::new();
+        let mut deck = TapeDeck::new();
+        deck.insert(Tape::from_programs(vec![p_program("T")], TapeFormat::Zx81));
+        assert_eq!(io.read_port(0xFE, 0xFF, &deck) & 0x80, 0);
+
+        // The leader is silence, the first bit's pulse is high
+        deck.play();
+        assert_eq!(io.read_port(0xFE, 0xFF, &deck) & 0x80, 0);
+        let mut now = 0;
+        while !deck.ear_level() {
+            assert!(now < 10 * CLOCK_HZ);
+            deck.advance(now, 100, 0);
+            now += 100;
         }
+        // Whatever rows are selected
+        assert_eq!(io.read_port(0xFE, 0xFF, &deck) & 0x80, 0x80);
+        assert_eq!(io.read_port(0xFE, 0x00, &deck) & 0x80, 0x80);
     }
 
-    pub fn write_port(&mut self, _port: u8, _value: u8) {}
+    #[test]
+    fn odd_ports_go_to_the_joystick() {
+        let deck = TapeDeck::new();
+        let mut io = IoController::new();
+        io.keyboard_state[0][1] = true;
+        assert_eq!(io.read_port(0x1F, 0x00, &deck), 0xFF);
+        assert_eq!(io.take_ear_reads(), 0);
+
+        io.joystick = Some(Joystick::new(JoystickInterface::Kempston));
+        io.joystick_mut().unwrap().set_state(JoystickState {
+            fire: true,
+            ..JoystickState::default()
+        });
+        assert_eq!(io.read_port(0x1F, 0x00, &deck), 0x10);
+        // Ports the interface doesn't decode float high
+        assert_eq!(io.read_port(0x3F, 0x00, &deck), 0xFF);
+    }
 }
@@ -0,0 +1,270 @@
+use std::collections::VecDeque;
+
+use minifb::Key;
+
+use super::keymap::Keymap;
+
+// Frames a typed character's keys are held down, then let go before the next one,
+// long enough for the ROM's once-a-frame scan to see both
+const HOLD_FRAMES: u32 = 3;
+const RELEASE_FRAMES: u32 = 2;
+
+// What each matrix position types on a ZX81, unshifted and with SHIFT ('\0' for
+// SHIFT itself, and for keywords and edit keys, which have no character)
+pub(super) const UNSHIFTED: [[char; 5]; 8] = [
+    ['\0', 'Z', 'X', 'C', 'V'],
+    ['A', 'S', 'D', 'F', 'G'],
+    ['Q', 'W', 'E', 'R', 'T'],
+    ['1', '2', '3', '4', '5'],
+    ['0', '9', '8', '7', '6'],
+    ['P', 'O', 'I', 'U', 'Y'],
+    ['\n', 'L', 'K', 'J', 'H'],
+    [' ', '.', 'M', 'N', 'B'],
+];
+const SHIFTED: [[char; 5]; 8] = [
+    ['\0', ':', ';', '?', '/'],
+    ['\0', '\0', '\0', '\0', '\0'],
+    ['\0', '\0', '\0', '\0', '\0'],
+    ['\0', '\0', '\0', '\0', '\0'],
+    ['\0', '\0', '\0', '\0', '\0'],
+    ['"', ')', '(', '$', '\0'],
+    ['\0', '=', '+', '-', '\0'],
+    ['£', ',', '>', '<', '*'],
+];
+
+// A key on the matrix, with or without SHIFT
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Chord {
+    pub shift: bool,
+    pub row: usize,
+    pub col: usize,
+}
+
+impl Chord {
+    pub const SHIFT: (usize, usize) = (0, 0);
+
+    pub fn shifted(row: usize, col: usize) -> Self {
+        Self {
+            shift: true,
+            row,
+            col,
+        }
+    }
+
+    // The keys that type `c` in L mode, if there are any
+    pub fn for_char(c: char) -> Option<Self> {
+        let c = c.to_ascii_uppercase();
+        let find = |table: &[[char; 5]; 8], shift| {
+            table.iter().enumerate().find_map(|(row, keys)| {
+                let col = keys.iter().position(|&k| k == c)?;
+                Some(Self { shift, row, col })
+            })
+        };
+        if c == '\0' {
+            return None;
+        }
+        find(&UNSHIFTED, false).or_else(|| find(&SHIFTED, true))
+    }
+
+    // Host keys with no key of their own on a ZX81, but a SHIFT chord that does the same job
+    pub fn for_host_key(key: Key) -> Option<Self> {
+        match key {
+            Key::Backspace | Key::Delete => Some(Self::shifted(4, 0)), // RUBOUT
+            Key::Left => Some(Self::shifted(3, 4)),
+            Key::Down => Some(Self::shifted(4, 4)),
+            Key::Up => Some(Self::shifted(4, 3)),
+            Key::Right => Some(Self::shifted(4, 2)),
+            _ => None,
+        }
+    }
+
+    pub fn press(&self, state: &mut [[bool; 5]; 8]) {
+        if self.shift {
+            state[Self::SHIFT.0][Self::SHIFT.1] = true;
+        }
+        state[self.row][self.col] = true;
+    }
+}
+
+// Turns what's typed on a PC keyboard into what a ZX81 keyboard needs to type the
+// same thing. Letters, digits, SPACE, ENTER and . stay on their own keys (SHIFT
+// with a letter still gives its keyword); editing keys become SHIFT chords while
+// they're held; symbols arrive as characters, whichever host keys made them, and
+// are typed as their chords one at a time.
+#[derive(Default)]
+pub struct KeyTranslator {
+    typed: VecDeque<Chord>, // Characters waiting their turn
+    current: Option<Chord>, // The typed character being held down
+    frames_left: u32,       // Of holding it, or of the gap after it
+}
+
+impl KeyTranslator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Work out this frame's matrix from the host keys held and the characters typed
+    // since the last frame
+    pub fn update(
+        &mut self,
+        keymap: &Keymap,
+        keys: &[Key],
+        typed: &[char],
+        state: &mut [[bool; 5]; 8],
+    ) {
+        let host_shift = keys.contains(&Key::LeftShift) || keys.contains(&Key::RightShift);
+        // A key the keymap gives a chord of its own types what the keymap says
+        let own_chord = keys
+            .iter()
+            .any(|&key| keymap.matrix(key).is_some_and(|keys| keys.len() > 1));
+        for &c in typed {
+            if !c.is_control() && !own_chord && !Self::types_directly(c, host_shift) {
+                self.typed.extend(Chord::for_char(c));
+            }
+        }
+
+        // A typed character has the keyboard to itself while it's pressed and
+        // released, so nothing held on the host turns it into something else
+        if self.frames_left == 0 {
+            match self.current.take() {
+                Some(_) => self.frames_left = RELEASE_FRAMES,
+                None => {
+                    self.current = self.typed.pop_front();
+                    if self.current.is_some() {
+                        self.frames_left = HOLD_FRAMES;
+                    }
+                }
+            }
+        }
+        if self.frames_left > 0 {
+            self.frames_left -= 1;
+            if let Some(chord) = self.current {
+                chord.press(state);
+            }
+            return;
+        }
+
+        // Host SHIFT with a digit or a symbol key types the host's symbol, which
+        // comes in as a character, so neither key goes through as itself
+        let mut shift_through = host_shift;
+        for &key in keys {
+            match keymap.matrix(key) {
+                Some(&[position]) if position == Chord::SHIFT => {}
+                Some(&[(row, col)]) if !host_shift || Self::keeps_shift(row, col) => {
+                    state[row][col] = true;
+                }
+                Some(positions) if positions.len() > 1 => {
+                    for &(row, col) in positions {
+                        state[row][col] = true;
+                    }
+                    shift_through = false;
+                }
+                Some(_) => shift_through = false,
+                None => match Chord::for_host_key(key) {
+                    Some(chord) => chord.press(state),
+                    None => shift_through = false,
+                },
+            }
+        }
+        if shift_through {
+            state[Chord::SHIFT.0][Chord::SHIFT.1] = true;
+        }
+    }
+
+    // Letters, SPACE and ENTER, whose SHIFT meanings are the ZX81's own
+    fn keeps_shift(row: usize, col: usize) -> bool {
+        UNSHIFTED[row][col].is_ascii_alphabetic() || matches!(UNSHIFTED[row][col], ' ' | '\n')
+    }
+
+    // Whether the host key that typed `c` is already down on the matrix
+    fn types_directly(c: char, host_shift: bool) -> bool {
+        c.is_ascii_alphabetic() || c == ' ' || (!host_shift && (c.is_ascii_digit() || c == '.'))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::SINCLAIR_LAYOUT;
+    use super::*;
+
+    const SHIFT: (usize, usize) = Chord::SHIFT;
+    const P: (usize, usize) = (5, 0);
+
+    // The matrix positions down after one frame
+    fn frame(translator: &mut KeyTranslator, keys: &[Key], typed: &[char]) -> Vec<(usize, usize)> {
+        let mut state = [[false; 5]; 8];
+        translator.update(&Keymap::new(&SINCLAIR_LAYOUT), keys, typed, &mut state);
+        (0..8)
+            .flat_map(|row| (0..5).map(move |col| (row, col)))
+            .filter(|&(row, col)| state[row][col])
+            .collect()
+    }
+
+    #[test]
+    fn symbols_come_from_the_shifted_table() {
+        assert_eq!(Chord::for_char('"'), Some(Chord::shifted(5, 0)));
+        assert_eq!(Chord::for_char('+'), Some(Chord::shifted(6, 2)));
+        assert_eq!(Chord::for_char('£'), Some(Chord::shifted(7, 0)));
+        assert_eq!(
+            Chord::for_char('a'),
+            Some(Chord {
+                shift: false,
+                row: 1,
+                col: 0
+            })
+        );
+        // Nothing on a ZX81 types these
+        assert_eq!(Chord::for_char('!'), None);
+        assert_eq!(Chord::for_char('\0'), None);
+    }
+
+    #[test]
+    fn typed_symbols_hold_then_release() {
+        let mut translator = KeyTranslator::new();
+        // Host SHIFT+' types '"', and a '+' arrives in the same frame
+        let keys = [Key::LeftShift, Key::Apostrophe];
+        assert_eq!(frame(&mut translator, &keys, &['"', '+']), vec![SHIFT, P]);
+        for _ in 1..HOLD_FRAMES {
+            assert_eq!(frame(&mut translator, &keys, &[]), vec![SHIFT, P]);
+        }
+        for _ in 0..RELEASE_FRAMES {
+            assert_eq!(frame(&mut translator, &keys, &[]), vec![]);
+        }
+        for _ in 0..HOLD_FRAMES {
+            assert_eq!(frame(&mut translator, &[], &[]), vec![SHIFT, (6, 2)]);
+        }
+        for _ in 0..RELEASE_FRAMES {
+            assert_eq!(frame(&mut translator, &[], &[]), vec![]);
+        }
+        assert_eq!(frame(&mut translator, &[Key::A], &[]), vec![(1, 0)]);
+    }
+
+    #[test]
+    fn host_shift_doesnt_reach_the_matrix_with_a_symbol() {
+        let mut translator = KeyTranslator::new();
+        // SHIFT+1 on the host is '!', which a ZX81 can't type: neither SHIFT nor 1
+        // goes through, as together they'd be EDIT
+        let keys = [Key::LeftShift, Key::Key1];
+        assert_eq!(frame(&mut translator, &keys, &['!']), vec![]);
+        // SHIFT with a letter is still the ZX81's SHIFT
+        let keys = [Key::LeftShift, Key::A];
+        assert_eq!(frame(&mut translator, &keys, &['A']), vec![SHIFT, (1, 0)]);
+        // And a digit on its own types itself
+        assert_eq!(frame(&mut translator, &[Key::Key1], &['1']), vec![(3, 0)]);
+    }
+
+    #[test]
+    fn editing_keys_are_shift_chords() {
+        let mut translator = KeyTranslator::new();
+        for (key, digit) in [
+            (Key::Left, (3, 4)),
+            (Key::Down, (4, 4)),
+            (Key::Up, (4, 3)),
+            (Key::Right, (4, 2)),
+            (Key::Backspace, (4, 0)),
+            (Key::Delete, (4, 0)),
+        ] {
+            assert_eq!(frame(&mut translator, &[key], &[]), vec![SHIFT, digit]);
+        }
+    }
+}
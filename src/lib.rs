@@ -1,7 +1,10 @@
+pub mod charset;
 pub mod cpu;
 pub mod emulator;
 pub mod io;
+pub mod machine;
 pub mod memory;
 pub mod tape;
+pub mod traps;
 pub mod video;
 pub use emulator::Emulator;
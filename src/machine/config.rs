@@ -0,0 +1,192 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use super::{Machine, T_STATES_PER_LINE, TvStandard};
+use crate::io::{JoystickInterface, Keymap};
+use crate::memory::{RomInfo, rom_info};
+use crate::traps::TapeMode;
+
+// Options for the window the emulator draws into
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrontendOptions {
+    pub headless: bool, // No window at all, for scripted and test runs
+    pub debug_panel: bool,
+    pub rev_video: bool,
+    pub scale: usize,     // Screen scale factor (to fit modern displays)
+    pub smart_keys: bool, // Type host symbols and editing keys as their ZX81 SHIFT chords
+}
+
+impl Default for FrontendOptions {
+    fn default() -> Self {
+        Self {
+            headless: false,
+            debug_panel: false,
+            rev_video: false,
+            scale: 3,
+            smart_keys: false,
+        }
+    }
+}
+
+// Everything needed to build an emulator
+// Defaults follow the identified ROM, the builder overrides any of them
+pub struct MachineConfig {
+    pub machine: Machine,
+    pub rom: Vec<u8>,
+    pub rom_info: &'static RomInfo,
+    pub clock_hz: u64,
+    pub ram_size: usize, // Bytes of RAM from 0x4000
+    pub tv_standard: TvStandard,
+    pub tape_mode: TapeMode,
+    pub save_dir: PathBuf, // Where SAVE writes its .p files
+    pub frontend: FrontendOptions,
+    pub keymap: Keymap, // Host keys to ZX81 keys and emulator actions
+    pub joystick: Option<JoystickInterface>,
+}
+
+impl MachineConfig {
+    pub fn builder(rom: Vec<u8>) -> MachineConfigBuilder {
+        MachineConfigBuilder::new(rom)
+    }
+
+    pub fn cycles_per_frame(&self) -> u64 {
+        self.tv_standard.lines_per_frame() * T_STATES_PER_LINE
+    }
+
+    // Real time one emulated frame should take
+    pub fn frame_duration(&self) -> Duration {
+        Duration::from_nanos(self.cycles_per_frame() * 1_000_000_000 / self.clock_hz)
+    }
+}
+
+pub struct MachineConfigBuilder {
+    rom: Vec<u8>,
+    machine: Option<Machine>,
+    clock_hz: Option<u64>,
+    ram_size: Option<usize>,
+    tv_standard: Option<TvStandard>,
+    tape_mode: Option<TapeMode>,
+    save_dir: PathBuf,
+    frontend: FrontendOptions,
+    keymap: Option<Keymap>,
+    joystick: Option<JoystickInterface>,
+}
+
+impl MachineConfigBuilder {
+    pub fn new(rom: Vec<u8>) -> Self {
+        Self {
+            rom,
+            machine: None,
+            clock_hz: None,
+            ram_size: None,
+            tv_standard: None,
+            tape_mode: None,
+            save_dir: PathBuf::from("."),
+            frontend: FrontendOptions::default(),
+            keymap: None,
+            joystick: None,
+        }
+    }
+
+    // Needed when the ROM alone can't tell us (e.g. ZX81 vs TS1000)
+    pub fn machine(mut self, machine: Machine) -> Self {
+        self.machine = Some(machine);
+        self
+    }
+
+    pub fn clock_hz(mut self, clock_hz: u64) -> Self {
+        self.clock_hz = Some(clock_hz);
+        self
+    }
+
+    pub fn ram_size(mut self, ram_size: usize) -> Self {
+        self.ram_size = Some(ram_size);
+        self
+    }
+
+    pub fn tv_standard(mut self, tv_standard: TvStandard) -> Self {
+        self.tv_standard = Some(tv_standard);
+        self
+    }
+
+    pub fn tape_mode(mut self, tape_mode: TapeMode) -> Self {
+        self.tape_mode = Some(tape_mode);
+        self
+    }
+
+    pub fn save_dir(mut self, save_dir: impl Into<PathBuf>) -> Self {
+        self.save_dir = save_dir.into();
+        self
+    }
+
+    pub fn frontend(mut self, frontend: FrontendOptions) -> Self {
+        self.frontend = frontend;
+        self
+    }
+
+    pub fn headless(mut self, headless: bool) -> Self {
+        self.frontend.headless = headless;
+        self
+    }
+
+    pub fn debug_panel(mut self, debug_panel: bool) -> Self {
+        self.frontend.debug_panel = debug_panel;
+        self
+    }
+
+    pub fn rev_video(mut self, rev_video: bool) -> Self {
+        self.frontend.rev_video = rev_video;
+        self
+    }
+
+    pub fn scale(mut self, scale: usize) -> Self {
+        self.frontend.scale = scale.max(1);
+        self
+    }
+
+    pub fn smart_keys(mut self, smart_keys: bool) -> Self {
+        self.frontend.smart_keys = smart_keys;
+        self
+    }
+
+    // Defaults to the machine's own layout with the function keys for the tape deck
+    pub fn keymap(mut self, keymap: Keymap) -> Self {
+        self.keymap = Some(keymap);
+        self
+    }
+
+    // No joystick unless one is plugged in
+    pub fn joystick(mut self, joystick: JoystickInterface) -> Self {
+        self.joystick = Some(joystick);
+        self
+    }
+
+    pub fn build(self) -> MachineConfig {
+        let rom_info = rom_info(&self.rom);
+        let machine = self.machine.unwrap_or(rom_info.machine);
+
+        // Only trap the tape routines where we know where they are
+        let tape_mode = match (self.tape_mode, rom_info.tape_traps) {
+            (Some(TapeMode::Turbo), _) => TapeMode::Turbo,
+            (_, None) => TapeMode::Rom,
+            (Some(mode), Some(_)) => mode,
+            (None, Some(_)) => TapeMode::Trapped,
+        };
+
+        MachineConfig {
+            machine,
+            rom_info,
+            clock_hz: self.clock_hz.unwrap_or(machine.clock_hz()),
+            ram_size: self.ram_size.unwrap_or(machine.default_ram_size()),
+            tv_standard: self.tv_standard.unwrap_or(machine.tv_standard()),
+            tape_mode,
+            save_dir: self.save_dir,
+            frontend: self.frontend,
+            keymap: self
+                .keymap
+                .unwrap_or_else(|| Keymap::new(machine.keyboard_layout())),
+            joystick: self.joystick,
+            rom: self.rom,
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use crate::io::{KeyboardLayout, SINCLAIR_LAYOUT};
+
+mod config;
+pub use config::{FrontendOptions, MachineConfig, MachineConfigBuilder};
+
+// Every machine here runs its Z80 at 3.25MHz and produces one scanline every 207 T-states
+pub const CLOCK_HZ: u64 = 3_250_000;
+pub const T_STATES_PER_LINE: u64 = 207;
+
+// TV standard the machine's video output is built for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TvStandard {
+    Pal,  // 50Hz, UK and Europe
+    Ntsc, // 60Hz, USA
+}
+
+impl TvStandard {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TvStandard::Pal => "PAL",
+            TvStandard::Ntsc => "NTSC",
+        }
+    }
+
+    pub fn frame_rate_hz(&self) -> u64 {
+        match self {
+            TvStandard::Pal => 50,
+            TvStandard::Ntsc => 60,
+        }
+    }
+
+    // Lines the ULA generates per frame (including vertical sync and blanking)
+    pub fn lines_per_frame(&self) -> u64 {
+        match self {
+            TvStandard::Pal => 310,
+            TvStandard::Ntsc => 262,
+        }
+    }
+}
+
+// Machine types the emulator knows about
+// Most of the hardware is shared, the differences are ROM, RAM fitted and TV standard
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Machine {
+    Zx81,
+    Ts1000,
+    Ts1500,
+    Zx80,
+}
+
+impl Machine {
+    // Parse a machine name as given on the command line
+    pub fn from_name(name: &str) -> Option<Machine> {
+        match name.to_ascii_lowercase().as_str() {
+            "zx81" => Some(Machine::Zx81),
+            "ts1000" => Some(Machine::Ts1000),
+            "ts1500" => Some(Machine::Ts1500),
+            "zx80" => Some(Machine::Zx80),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Machine::Zx81 => "Sinclair ZX81",
+            Machine::Ts1000 => "Timex Sinclair 1000",
+            Machine::Ts1500 => "Timex Sinclair 1500",
+            Machine::Zx80 => "Sinclair ZX80",
+        }
+    }
+
+    // RAM fitted when nothing else is asked for
+    pub fn default_ram_size(&self) -> usize {
+        match self {
+            Machine::Zx81 => 0x4000,   // Assume the 16K RAM pack, most software needs it
+            Machine::Ts1000 => 0x0800, // 2K on board
+            Machine::Ts1500 => 0x4000, // 16K on board
+            Machine::Zx80 => 0x0400,   // 1K on board
+        }
+    }
+
+    pub fn tv_standard(&self) -> TvStandard {
+        match self {
+            Machine::Ts1000 | Machine::Ts1500 => TvStandard::Ntsc,
+            Machine::Zx81 | Machine::Zx80 => TvStandard::Pal,
+        }
+    }
+
+    pub fn clock_hz(&self) -> u64 {
+        CLOCK_HZ
+    }
+
+    // Every machine here wires its keys to the same matrix positions
+    pub fn keyboard_layout(&self) -> &'static KeyboardLayout {
+        &SINCLAIR_LAYOUT
+    }
+
+    // The ZX80 has no NMI generator: the picture only exists while the ROM is
+    // producing it, which it only does while waiting for a key
+    pub fn has_nmi_display(&self) -> bool {
+        !matches!(self, Machine::Zx80)
+    }
+}
@@ -1,9 +1,14 @@
 use std::env;
+use std::fs;
+use std::path::Path;
 use std::process;
 
 use zx81_emulator::Emulator;
-use zx81_emulator::memory::load_rom;
-use zx81_emulator::tape::Tape;
+use zx81_emulator::io::{Action, JoystickInterface};
+use zx81_emulator::machine::{Machine, MachineConfig};
+use zx81_emulator::memory::{ZX80_ROM_SIZE, crc32, identify_rom, load_rom};
+use zx81_emulator::tape::{Degradation, Tape, TapeEvent};
+use zx81_emulator::traps::TapeMode;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
@@ -11,7 +16,7 @@ fn main() {
     // We need a minimum of 2 args
     if args.len() < 2 {
         eprintln!(
-            "Usage: {} <rom_file> [--debug] [--video-debug] [--rev-video]",
+            "Usage: {} <rom_file> [tape_file...] [--debug] [--video-debug] [--rev-video] [--real-tape] [--turbo-tape] [--no-auto-play] [--autostart] [--smart-keys] [--keymap=<file>] [--type=<text>] [--joystick=<cursor|kempston|zxpand>] [--machine=<zx81|ts1000|ts1500|zx80>] [--ram=<KB>] [--save-dir=<dir>] [--extract=<dir>] [--convert=<file.wav|file.p81|dir>] [--sample-rate=<Hz>] [--amplitude=<percent>] [--record-mic=<file.wav|file.p81|dir>] [--tape-degrade=<spec>]",
             args[0]
         );
         process::exit(1);
@@ -20,6 +25,47 @@ fn main() {
     // Check if debug is enabled
     let debug_enabled: bool = args.contains(&"--debug".to_string());
     let rev_video: bool = args.contains(&"--rev-video".to_string());
+    let real_tape: bool = args.contains(&"--real-tape".to_string());
+    let turbo_tape: bool = args.contains(&"--turbo-tape".to_string());
+    let no_auto_play: bool = args.contains(&"--no-auto-play".to_string());
+    let autostart: bool = args.contains(&"--autostart".to_string());
+    let smart_keys: bool = args.contains(&"--smart-keys".to_string());
+
+    // Machine override, for machines that share a ROM (e.g. ZX81 and TS1000)
+    let machine_override = args
+        .iter()
+        .find_map(|arg| arg.strip_prefix("--machine="))
+        .map(|name| match Machine::from_name(name) {
+            Some(machine) => machine,
+            None => {
+                eprintln!("Unknown machine: {}", name);
+                process::exit(1);
+            }
+        });
+
+    // Joystick interface plugged in, worked from the numeric keypad by default
+    let joystick = args
+        .iter()
+        .find_map(|arg| arg.strip_prefix("--joystick="))
+        .map(|name| match JoystickInterface::from_name(name) {
+            Some(interface) => interface,
+            None => {
+                eprintln!("Unknown joystick interface: {}", name);
+                process::exit(1);
+            }
+        });
+
+    // RAM override in KB, fitted from 0x4000
+    let ram_override = args
+        .iter()
+        .find_map(|arg| arg.strip_prefix("--ram="))
+        .map(|kb| match kb.parse::<usize>() {
+            Ok(kb) if (1..=16).contains(&kb) => kb * 1024,
+            _ => {
+                eprintln!("Invalid RAM size: {} (expected 1-16 KB)", kb);
+                process::exit(1);
+            }
+        });
 
     if debug_enabled {
         println!("Debug mode enabled...");
@@ -33,28 +79,178 @@ fn main() {
         println!("Video colour reversal disabled...");
     }
 
+    if turbo_tape {
+        println!("Using ROM tape routines (turbo: no throttling or redraws while loading)...");
+    } else if real_tape {
+        println!("Using ROM tape routines (real-time loading)...");
+    } else {
+        println!("Using trapped tape routines (fast loading)...");
+    }
+
+    // Directory SAVE writes .p files to
+    let save_dir = args
+        .iter()
+        .find_map(|arg| arg.strip_prefix("--save-dir="))
+        .map(|dir| dir.to_string());
+
+    // Directory to decode the tape's programs into, instead of running
+    let extract_dir = args
+        .iter()
+        .find_map(|arg| arg.strip_prefix("--extract="))
+        .map(|dir| dir.to_string());
+
+    // File to convert the tape into (.wav, .p81/.81 or a directory of .p files), instead of running
+    let convert_path = args
+        .iter()
+        .find_map(|arg| arg.strip_prefix("--convert="))
+        .map(|path| path.to_string());
+
+    // Where to write whatever the machine sends to the MIC socket, when it exits
+    let record_mic_path = args
+        .iter()
+        .find_map(|arg| arg.strip_prefix("--record-mic="))
+        .map(|path| path.to_string());
+
+    // Host key bindings to apply over the machine's own layout
+    let keymap_path = args
+        .iter()
+        .find_map(|arg| arg.strip_prefix("--keymap="))
+        .map(|path| path.to_string());
+
+    // Text to type in once the machine is ready, with \n for ENTER
+    let type_text = args
+        .iter()
+        .find_map(|arg| arg.strip_prefix("--type="))
+        .map(|text| text.replace("\\n", "\n"));
+
+    // Spoil the tape signal on purpose, to see how a loader copes
+    let degradation = args
+        .iter()
+        .find_map(|arg| arg.strip_prefix("--tape-degrade="))
+        .map(|spec| match spec.parse::<Degradation>() {
+            Ok(degradation) => degradation,
+            Err(e) => {
+                eprintln!("Invalid --tape-degrade: {}", e);
+                process::exit(1);
+            }
+        });
+
+    // WAV export settings
+    let sample_rate = args
+        .iter()
+        .find_map(|arg| arg.strip_prefix("--sample-rate="))
+        .map_or(44100, |rate| match rate.parse::<u32>() {
+            Ok(rate) if (8000..=192000).contains(&rate) => rate,
+            _ => {
+                eprintln!("Invalid sample rate: {} (expected 8000-192000)", rate);
+                process::exit(1);
+            }
+        });
+    let amplitude = args
+        .iter()
+        .find_map(|arg| arg.strip_prefix("--amplitude="))
+        .map_or(0.8, |percent| match percent.parse::<u32>() {
+            Ok(percent) if (1..=100).contains(&percent) => percent as f32 / 100.0,
+            _ => {
+                eprintln!("Invalid amplitude: {} (expected 1-100%)", percent);
+                process::exit(1);
+            }
+        });
+
     // Remove --debug from args if it did exist
     let args: Vec<String> = args
         .into_iter()
-        .filter(|arg| arg != "--debug" && arg != "--rev-video")
+        .filter(|arg| {
+            arg != "--debug"
+                && arg != "--rev-video"
+                && arg != "--real-tape"
+                && arg != "--turbo-tape"
+                && arg != "--no-auto-play"
+                && arg != "--autostart"
+                && arg != "--smart-keys"
+                && !arg.starts_with("--machine=")
+                && !arg.starts_with("--ram=")
+                && !arg.starts_with("--save-dir=")
+                && !arg.starts_with("--extract=")
+                && !arg.starts_with("--convert=")
+                && !arg.starts_with("--sample-rate=")
+                && !arg.starts_with("--amplitude=")
+                && !arg.starts_with("--record-mic=")
+                && !arg.starts_with("--tape-degrade=")
+                && !arg.starts_with("--keymap=")
+                && !arg.starts_with("--type=")
+                && !arg.starts_with("--joystick=")
+        })
         .collect();
 
+    // Write out every program on the tape as a .p file and stop, e.g. to check a WAV dump
+    if let Some(dir) = extract_dir {
+        if args.len() < 3 {
+            eprintln!("--extract needs a tape to extract from");
+            process::exit(1);
+        }
+        let tape = open_tape(&args[2]);
+        if let Err(e) = fs::create_dir_all(&dir) {
+            eprintln!("Error: Failed to create {}: {}", dir, e);
+            process::exit(1);
+        }
+        match tape.extract_p(Path::new(&dir)) {
+            Ok(paths) => {
+                for path in &paths {
+                    println!("Extracted {}", path.display());
+                }
+                println!("{} program(s) extracted", paths.len());
+                process::exit(0);
+            }
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                process::exit(1);
+            }
+        }
+    }
+
+    // Convert the tape to audio or a .p81 and stop
+    if let Some(path) = convert_path {
+        if args.len() < 3 {
+            eprintln!("--convert needs a tape to convert");
+            process::exit(1);
+        }
+        let tape = open_tape(&args[2]);
+        match write_tape(&tape, Path::new(&path), sample_rate, amplitude) {
+            Ok(()) => {
+                println!("Converted {} to {}", args[2], path);
+                process::exit(0);
+            }
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                process::exit(1);
+            }
+        }
+    }
+
     // Load ROM file from args[1]
     let rom = match load_rom(&args[1]) {
         Ok(data) => {
             println!("Loaded ROM: {} ({} bytes)", args[1], data.len());
 
-            // == ROM Sanity Checks == //
+            // == ROM Identification == //
 
-            if data.len() != 8192 {
-                eprintln!("WARNING: ROM size is {} bytes, expected 8192", data.len());
-            }
-
-            if data.len() > 3 {
-                println!(
-                    "ROM Signature: {:02X} {:02X} {:02X}",
-                    data[0], data[1], data[2]
-                );
+            match identify_rom(&data) {
+                Some(info) => println!(
+                    "ROM identified: {} ({}, CRC32 {:08X})",
+                    info.name,
+                    info.machine.name(),
+                    info.crc32
+                ),
+                None => eprintln!(
+                    "WARNING: Unknown ROM (CRC32 {:08X}), assuming a standard {} layout",
+                    crc32(&data),
+                    if data.len() == ZX80_ROM_SIZE {
+                        "ZX80"
+                    } else {
+                        "ZX81"
+                    }
+                ),
             }
 
             data
@@ -65,7 +261,47 @@ fn main() {
         }
     };
 
-    let mut emulator = match Emulator::new(rom, debug_enabled, rev_video) {
+    let mut builder = MachineConfig::builder(rom)
+        .debug_panel(debug_enabled)
+        .rev_video(rev_video)
+        .smart_keys(smart_keys);
+    if let Some(machine) = machine_override {
+        builder = builder.machine(machine);
+    }
+    if let Some(ram_size) = ram_override {
+        builder = builder.ram_size(ram_size);
+    }
+    if let Some(joystick) = joystick {
+        println!("Joystick interface: {}", joystick.name());
+        builder = builder.joystick(joystick);
+    }
+    if turbo_tape {
+        builder = builder.tape_mode(TapeMode::Turbo);
+    } else if real_tape {
+        builder = builder.tape_mode(TapeMode::Rom);
+    }
+    if let Some(save_dir) = save_dir {
+        builder = builder.save_dir(save_dir);
+    }
+    let mut config = builder.build();
+    if let Some(path) = keymap_path {
+        if let Err(e) = config.keymap.load_file(&path) {
+            eprintln!("Invalid keymap: {}", e);
+            process::exit(1);
+        }
+        println!("Loaded keymap: {}", path);
+    }
+
+    println!(
+        "Machine: {} ({}, {}Hz, {} lines, {}K RAM)",
+        config.machine.name(),
+        config.tv_standard.name(),
+        config.tv_standard.frame_rate_hz(),
+        config.tv_standard.lines_per_frame(),
+        config.ram_size / 1024
+    );
+
+    let mut emulator = match Emulator::new(config) {
         Ok(emu) => emu,
         Err(e) => {
             eprintln!("Failed to create emulator: {}", e);
@@ -73,23 +309,87 @@ fn main() {
         }
     };
 
-    if args.len() > 2 {
-        let p_file_path = &args[2];
-        let tape_data = Tape::new(&p_file_path);
-        emulator.load_tape(tape_data)
+    // With --autostart the first program goes straight into memory, anything after
+    // it waits in the deck
+    let mut tape_paths = args.iter().skip(2);
+    if autostart && let Some(tape_path) = tape_paths.next() {
+        let mut tape = open_tape(tape_path);
+        let data = match tape.take_program(None) {
+            Some(program) => program.data.clone(),
+            None => {
+                eprintln!("ERROR: {} has no program to autostart", tape_path);
+                process::exit(1);
+            }
+        };
+        match emulator.quick_load(&data) {
+            Ok(sysvars) => match sysvars.and_then(|sysvars| sysvars.autostart()) {
+                Some(nxtlin) => println!(
+                    "Quick-loaded {} bytes, running from 0x{:04X}",
+                    data.len(),
+                    nxtlin
+                ),
+                None => println!("Quick-loaded {} bytes", data.len()),
+            },
+            Err(e) => {
+                eprintln!("ERROR: {}", e);
+                process::exit(1);
+            }
+        }
+        if tape.has_program(None) {
+            tape.set_degradation(degradation.clone());
+            emulator.load_tape(tape);
+        }
+    }
+
+    // Every tape given goes into the deck's queue, in order
+    for tape_path in tape_paths {
+        let mut tape = open_tape(tape_path);
+        tape.set_degradation(degradation.clone());
+        emulator.load_tape(tape);
+    }
+    emulator.deck_mut().set_auto_play(!no_auto_play);
+
+    if let Some(text) = type_text
+        && let Err(e) = emulator.type_text(&text)
+    {
+        eprintln!("ERROR: {}", e);
+        process::exit(1);
+    }
+
+    if record_mic_path.is_some() {
+        println!("Recording MIC output...");
+        emulator.start_mic_recording();
     }
 
     println!("Starting emulation...\n");
 
-    const CYCLES_PER_FRAME: u64 = 65000; // ~3.25MHz / 50Hz
+    let cycles_per_frame = emulator.cycles_per_frame();
+    let frame_duration = emulator.frame_duration();
     const INIT_FRAMES: u32 = 20; // Wait 20 frames (~400ms) before rendering
+    const TURBO_REDRAW_FRAMES: u32 = 50; // Keep the window alive during turbo loading
+    const TURBO_INPUT_FRAMES: u32 = 5; // And the keys working, so a turbo load can be stopped
 
     let mut total_cycles = 0u64;
     let mut frame_count = 0u32;
     let mut _frames_since_init = 0u32;
+    let mut paused = false;
 
     while emulator.is_window_open() {
-        let target_cycles = total_cycles + CYCLES_PER_FRAME;
+        report_tape_events(&mut emulator);
+
+        // Paused, the machine stands still but the window and its keys stay alive
+        if paused {
+            for action in emulator.update_keyboard() {
+                handle_action(&mut emulator, action, &mut paused);
+            }
+            emulator.update_display().unwrap_or_else(|e| {
+                eprintln!("Error updating display: {}", e);
+            });
+            std::thread::sleep(frame_duration);
+            continue;
+        }
+
+        let target_cycles = total_cycles + cycles_per_frame;
         let mut frame_instruction_count = 0;
 
         while total_cycles < target_cycles {
@@ -110,9 +410,21 @@ fn main() {
 
         frame_count += 1;
 
+        // While a loader is sampling the tape, run flat out and only redraw now and then
+        let turbo = emulator.tape_turbo_active();
+        if turbo && !frame_count.is_multiple_of(TURBO_REDRAW_FRAMES) {
+            if frame_count.is_multiple_of(TURBO_INPUT_FRAMES) {
+                emulator.poll_input();
+                for action in emulator.update_keyboard() {
+                    handle_action(&mut emulator, action, &mut paused);
+                }
+            }
+            continue;
+        }
+
         // Wait for init period
         if frame_count < INIT_FRAMES {
-            if frame_count % 5 == 0 {
+            if frame_count.is_multiple_of(5) {
                 println!("Initialising... frame {}/{}", frame_count, INIT_FRAMES);
             }
 
@@ -129,26 +441,9 @@ fn main() {
 
             _frames_since_init += 1;
 
-            // Get keyboard input
-            emulator.update_keyboard();
-
-            let keys = emulator.video().get_keys();
-            // Check for cassette play
-            if keys.contains(&minifb::Key::F5) {
-                if let Some(t) = &mut emulator.tape {
-                    if !t.is_playing() {
-                        t.start_playing();
-                    }
-                }
-            }
-            // Check for cassette stop
-            if keys.contains(&minifb::Key::F6) {
-                if let Some(t) = &mut emulator.tape {
-                    t.playing = false;
-                    t.current_index = 0;
-                    t.remaining = 0;
-                    println!("Tape stopped");
-                }
+            // Get keyboard input, and whatever the host keys bound to actions ask for
+            for action in emulator.update_keyboard() {
+                handle_action(&mut emulator, action, &mut paused);
             }
 
             // Render display
@@ -157,11 +452,151 @@ fn main() {
                 .unwrap_or_else(|e| eprintln!("Display error: {}", e));
         }
 
-        // Maintain ~50Hz refresh rate (ZX81 standard)
-        std::thread::sleep(std::time::Duration::from_millis(20));
+        // Maintain the machine's refresh rate (50Hz PAL, 60Hz NTSC)
+        if !turbo {
+            std::thread::sleep(frame_duration);
+        }
+    }
+
+    report_tape_events(&mut emulator);
+
+    if let Some(path) = record_mic_path
+        && let Some(tape) = emulator.stop_mic_recording()
+    {
+        for line in tape.summary() {
+            println!("{}", line);
+        }
+        match write_tape(&tape, Path::new(&path), sample_rate, amplitude) {
+            Ok(()) => println!("MIC recording written to {}", path),
+            Err(e) => eprintln!("Error: {}", e),
+        }
     }
 
     println!("\nEmulation stopped.");
     println!("Total frames: {}", frame_count);
     println!("Total cycles: {}", total_cycles);
 }
+
+// Carry out what a host key bound to an action asks for
+fn handle_action(emulator: &mut Emulator, action: Action, paused: &mut bool) {
+    let deck = emulator.deck_mut();
+    match action {
+        Action::TapePlay => deck.play(),
+        Action::TapeStop => deck.stop(),
+        Action::TapeRewind => deck.rewind(),
+        Action::TapeNext => deck.fast_forward(),
+        Action::TapePrevious => deck.previous_block(),
+        Action::TapeEject => {
+            deck.eject();
+        }
+        Action::Snapshot => {
+            match emulator.snapshot() {
+                Ok(path) => println!("Snapshot written to {}", path.display()),
+                Err(e) => eprintln!("ERROR: Can't write snapshot: {}", e),
+            }
+            return;
+        }
+        Action::Reset => {
+            println!("Reset");
+            emulator.reset();
+            return;
+        }
+        Action::Pause => {
+            *paused = !*paused;
+            println!("{}", if *paused { "Paused" } else { "Resumed" });
+            return;
+        }
+    }
+    println!("Tape deck: {}", emulator.deck().counter());
+}
+
+// Say what the tape deck and the LOAD/SAVE traps have been up to
+fn report_tape_events(emulator: &mut Emulator) {
+    for event in emulator.deck_mut().take_events() {
+        match event {
+            TapeEvent::Playing => println!("Tape playing started!"),
+            TapeEvent::Stopped => println!("Tape stopped"),
+            TapeEvent::Ended => println!("Tape ended"),
+            TapeEvent::Ejected { queued } => println!("Tape ejected ({} left in queue)", queued),
+            TapeEvent::Inserted { tape, of } => println!("Tape {} of {} inserted", tape, of),
+            TapeEvent::AutoStarted => println!("Loader listening, starting tape"),
+            TapeEvent::AutoStopped { counter } => {
+                println!("Loader finished, stopping tape at {}", counter)
+            }
+            TapeEvent::LoadRequested { name: Some(name) } => {
+                println!("LOAD hook triggered: \"{}\"", name)
+            }
+            TapeEvent::LoadRequested { name: None } => {
+                println!("LOAD hook triggered: next program")
+            }
+            TapeEvent::Loaded {
+                bytes,
+                sysvars: None,
+                ..
+            } => println!("Loaded {} bytes from tape", bytes),
+            TapeEvent::Loaded {
+                name,
+                bytes,
+                sysvars: Some(sysvars),
+            } => {
+                println!("Loaded \"{}\": {} bytes from tape", name, bytes);
+                println!(
+                    "System variables: D_FILE=0x{:04X} DF_CC=0x{:04X} VARS=0x{:04X} E_LINE=0x{:04X} NXTLIN=0x{:04X}",
+                    sysvars.d_file, sysvars.df_cc, sysvars.vars, sysvars.e_line, sysvars.nxtlin
+                );
+                if let Some(nxtlin) = sysvars.autostart() {
+                    // Line numbers are stored high byte first
+                    let memory = emulator.memory();
+                    let line = u16::from_be_bytes([memory.read(nxtlin), memory.read(nxtlin + 1)]);
+                    println!("Program runs on from line {}", line);
+                }
+            }
+            TapeEvent::LoadFailed { name, error } => {
+                eprintln!("ERROR: Can't load \"{}\": {}", name, error)
+            }
+            TapeEvent::NoTape => println!("No tape loaded!"),
+            TapeEvent::NotFound => println!("Program not found on tape!"),
+            TapeEvent::Saved { path, bytes } => {
+                println!("Saved {} bytes to {}", bytes, path.display())
+            }
+            TapeEvent::SaveFailed { error } => eprintln!("ERROR: {}", error),
+        }
+    }
+}
+
+// Load a tape given on the command line, or give up
+fn open_tape(path: &str) -> Tape {
+    println!("INFO: Loading tape: {}", path);
+    match Tape::from_path(path) {
+        Ok(tape) => {
+            for line in tape.summary() {
+                println!("{}", line);
+            }
+            tape
+        }
+        Err(e) => {
+            eprintln!("ERROR: Error loading tape {}: {}", path, e);
+            process::exit(1);
+        }
+    }
+}
+
+// Write a tape out by the destination's extension: audio, a .p81, or .p files in a directory
+fn write_tape(tape: &Tape, path: &Path, sample_rate: u32, amplitude: f32) -> Result<(), String> {
+    let extension = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "wav" => tape.save_wav(path, sample_rate, amplitude),
+        "p81" | "81" => tape.save_p81(path),
+        _ => {
+            fs::create_dir_all(path)
+                .map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+            for written in tape.extract_p(path)? {
+                println!("Extracted {}", written.display());
+            }
+            Ok(())
+        }
+    }
+}
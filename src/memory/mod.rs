@@ -1,6 +1,16 @@
 mod ram;
 mod rom;
-pub use rom::load_rom;
+pub use rom::{
+    KNOWN_ROMS, RomInfo, TapeTraps, ZX80_ROM_SIZE, ZX81_ROM_SIZE, crc32, identify_rom, load_rom,
+    rom_info,
+};
+
+use crate::tape::{PSysvars, TapeError, parse_o};
+
+const PROGRAM_START: u16 = 0x4009; // VERSN, where a .p image starts
+const E_LINE: u16 = 0x4014; // System variable marking where it ends
+const ZX80_PROGRAM_START: u16 = 0x4000; // A .o image starts with the system variables
+const ZX80_E_LINE: u16 = 0x400A;
 
 pub struct Memory {
     rom: Vec<u8>,
@@ -8,44 +18,10 @@ pub struct Memory {
 }
 
 impl Memory {
-    pub fn new(mut rom: Vec<u8>) -> Self {
-        // Patch the ROM with load/save hooks
-        Self::patch_zx81_hooks(&mut rom);
-
+    pub fn new(rom: Vec<u8>, ram_size: usize) -> Self {
         Self {
             rom,
-            ram: vec![0; 0x4000], // 16K RAM
-        }
-    }
-
-    fn patch_zx81_hooks(rom: &mut Vec<u8>) {
-        // Patch LOAD routine at 0x347
-        // Original ROM code will be replaced with:
-        // 0x347: EB        - EX DE,HL (save HL in DE)
-        // 0x348: ED FC     - Custom LOAD hook
-        // 0x34A: C3 07 02  - JP 0x0207 (back to ROM)
-        if rom.len() > 0x34C {
-            rom[0x347] = 0xEB;  // EX DE,HL
-            rom[0x348] = 0xED;  // ED prefix
-            rom[0x349] = 0xFC;  // LOAD hook opcode
-            rom[0x34A] = 0xC3;  // JP
-            rom[0x34B] = 0x07;
-            rom[0x34C] = 0x02;  // to 0x0207
-
-            println!("ROM: Patched LOAD routine at 0x0347");
-        }
-
-        // Patch SAVE routine at 0x2FC
-        // 0x2FC: ED FD     - Custom SAVE hook
-        // 0x2FE: C3 07 02  - JP 0x0207
-        if rom.len() > 0x300 {
-            rom[0x2FC] = 0xED;  // ED prefix
-            rom[0x2FD] = 0xFD;  // SAVE hook opcode
-            rom[0x2FE] = 0xC3;  // JP
-            rom[0x2FF] = 0x07;
-            rom[0x300] = 0x02;  // to 0x0207
-
-            println!("ROM: Patched SAVE routine at 0x02FC");
+            ram: vec![0; ram_size],
         }
     }
 
@@ -53,41 +29,76 @@ impl Memory {
         &self.rom
     }
 
-    pub fn load_program(&mut self, data: &[u8]) -> Result<(), String> {
-        if data.is_empty() {
-            return Err("Empty .p file".to_string());
+    // Put a .p image in place the way the ROM's LOAD does: every byte from VERSN up
+    // to E_LINE, its saved system variables included. Nothing is written unless they
+    // check out and the program fits in RAM.
+    pub fn load_program(&mut self, data: &[u8]) -> Result<PSysvars, TapeError> {
+        let sysvars = PSysvars::read(data)?;
+        let ram_end = 0x4000 + self.ram.len();
+        if sysvars.e_line as usize > ram_end {
+            return Err(TapeError::Corrupt(format!(
+                "needs RAM up to {:04X}, this machine's ends at {:04X}",
+                sysvars.e_line, ram_end
+            )));
         }
 
-        // Preserve 0x4000–0x4011
-        let mut saved = [0u8; 18];
-        for i in 0..18 {
-            saved[i] = self.read(0x4000 + i as u16);
+        for (addr, &byte) in (PROGRAM_START..).zip(data) {
+            self.write(addr, byte);
         }
+        Ok(sysvars)
+    }
 
-        // Load from 0x4009
-        for (i, &byte) in data.iter().enumerate() {
-            let addr = 0x4009 + i as u16;
-            if addr >= 0x8000 {
-                break;
-            }
-            self.write(addr, byte);
+    // The .p image a SAVE would write now: every byte from VERSN up to (but not
+    // including) E_LINE. None if E_LINE doesn't point past the program area.
+    pub fn save_program(&self) -> Option<Vec<u8>> {
+        let e_line = self.read_word(E_LINE);
+        if e_line <= PROGRAM_START {
+            return None;
         }
+        Some(
+            (PROGRAM_START..e_line)
+                .map(|addr| self.read(addr))
+                .collect(),
+        )
+    }
 
-        // Restore sysvars
-        for i in 0..18 {
-            self.write(0x4000 + i as u16, saved[i]);
+    // Put a .o image in place the way the ZX80's LOAD does: every byte from 0x4000
+    // up to E_LINE. Nothing is written unless E_LINE checks out and it fits in RAM.
+    pub fn load_zx80_program(&mut self, data: &[u8]) -> Result<usize, TapeError> {
+        let len = parse_o(data)?.data.len();
+        if len > self.ram.len() {
+            return Err(TapeError::Corrupt(format!(
+                "needs RAM up to {:04X}, this machine's ends at {:04X}",
+                ZX80_PROGRAM_START as usize + len,
+                ZX80_PROGRAM_START as usize + self.ram.len()
+            )));
         }
 
-        // Set STKEND
-        let end = 0x4009 + data.len() as u16;
-        self.write_word(0x401F, end);
+        for (addr, &byte) in (ZX80_PROGRAM_START..).zip(&data[..len]) {
+            self.write(addr, byte);
+        }
+        Ok(len)
+    }
 
-        Ok(())
+    // The .o image the ZX80's SAVE would write now: every byte from 0x4000 up to
+    // (but not including) E_LINE. None if E_LINE doesn't point past its own system
+    // variable.
+    pub fn save_zx80_program(&self) -> Option<Vec<u8>> {
+        let e_line = self.read_word(ZX80_E_LINE);
+        if e_line <= ZX80_E_LINE + 1 {
+            return None;
+        }
+        Some(
+            (ZX80_PROGRAM_START..e_line)
+                .map(|addr| self.read(addr))
+                .collect(),
+        )
     }
 
     pub fn read(&self, addr: u16) -> u8 {
         match addr {
-            0x0000..=0x1FFF => self.rom[addr as usize],
+            // 4K ROMs (ZX80) are mirrored through the ROM area
+            0x0000..=0x1FFF => self.rom[addr as usize % self.rom.len()],
             0x4000..=0x7FFF => {
                 let offset = (addr - 0x4000) as usize;
                 if offset < self.ram.len() {
@@ -1,15 +1,182 @@
 use std::fs;
 
-const ZX81_ROM_SIZE: usize = 0x2000;
+use crate::machine::Machine;
+use crate::tape::TapeFormat;
+
+pub const ZX81_ROM_SIZE: usize = 0x2000;
+pub const ZX80_ROM_SIZE: usize = 0x1000;
+
+// Addresses in a ROM where the LOAD/SAVE traps attach, and that quick-loading
+// needs to finish a LOAD the way the ROM would
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TapeTraps {
+    pub format: TapeFormat,    // The layout LOAD and SAVE use in memory
+    pub load: u16,             // Reached once any filename has been evaluated
+    pub save: u16,             // Reached once any filename has been evaluated
+    pub resume: u16,           // Where a trapped LOAD/SAVE carries on
+    pub keyboard: Option<u16>, // KEYBOARD, first called at the K prompt
+    pub line_run: Option<u16>, // Where a finished command runs on from NXTLIN
+}
+
+// Every Sinclair-derived 8K ROM keeps LOAD and SAVE in the same place
+const ZX81_TAPE_TRAPS: TapeTraps = TapeTraps {
+    format: TapeFormat::Zx81,
+    load: 0x0347,           // LOAD, just after NAME has been called
+    save: 0x02FC,           // SAVE, just after EX DE,HL
+    resume: 0x0207,         // SLOW/FAST, where LOAD and SAVE finish
+    keyboard: Some(0x02BB), // KEYBOARD
+    line_run: Some(0x0676), // LINE-RUN
+};
+
+// The ZX80's LOAD and SAVE take no name and both finish by going back to the
+// editor's main loop, which the ROM also passes through once it has booted
+const ZX80_TAPE_TRAPS: TapeTraps = TapeTraps {
+    format: TapeFormat::Zx80,
+    load: 0x0206,   // LOAD
+    save: 0x01B6,   // SAVE
+    resume: 0x0283, // Main loop
+    keyboard: None,
+    line_run: None,
+};
+
+#[derive(Debug)]
+pub struct RomInfo {
+    pub name: &'static str,
+    pub crc32: u32,
+    pub size: usize,
+    pub machine: Machine,
+    pub tape_traps: Option<TapeTraps>, // None where we don't know the ROM well enough to trap
+    pub charset_addr: u16,
+}
+
+// Known ROM images, identified by CRC32 of the whole file
+pub static KNOWN_ROMS: &[RomInfo] = &[
+    RomInfo {
+        name: "ZX81 issue 1",
+        crc32: 0xFCBBD617,
+        size: ZX81_ROM_SIZE,
+        machine: Machine::Zx81,
+        tape_traps: Some(ZX81_TAPE_TRAPS),
+        charset_addr: 0x1E00,
+    },
+    // Also fitted to the Timex Sinclair 1000, which can't be told apart by ROM alone
+    RomInfo {
+        name: "ZX81 issue 2 / TS1000",
+        crc32: 0x4B1DD6EB,
+        size: ZX81_ROM_SIZE,
+        machine: Machine::Zx81,
+        tape_traps: Some(ZX81_TAPE_TRAPS),
+        charset_addr: 0x1E00,
+    },
+    RomInfo {
+        name: "ZX81 issue 3 (improved)",
+        crc32: 0x522C37B8,
+        size: ZX81_ROM_SIZE,
+        machine: Machine::Zx81,
+        tape_traps: Some(ZX81_TAPE_TRAPS),
+        charset_addr: 0x1E00,
+    },
+    RomInfo {
+        name: "Timex Sinclair 1500",
+        crc32: 0x7DD19C48,
+        size: ZX81_ROM_SIZE,
+        machine: Machine::Ts1500,
+        tape_traps: Some(ZX81_TAPE_TRAPS),
+        charset_addr: 0x1E00,
+    },
+    // Third-party ZX81 clones. The PC8300's ROM differs from Sinclair's in places we
+    // haven't mapped, so LOAD and SAVE may not start where they do on a ZX81. A trap
+    // at the wrong address would fire in the middle of other code, so it keeps its
+    // own tape routines.
+    RomInfo {
+        name: "PC8300",
+        crc32: 0xA350F2B1,
+        size: ZX81_ROM_SIZE,
+        machine: Machine::Zx81,
+        tape_traps: None,
+        charset_addr: 0x1E00,
+    },
+    RomInfo {
+        name: "Ringo R-470",
+        crc32: 0xB9C5BC69,
+        size: ZX81_ROM_SIZE,
+        machine: Machine::Zx81,
+        tape_traps: Some(ZX81_TAPE_TRAPS),
+        charset_addr: 0x1E00,
+    },
+    RomInfo {
+        name: "ZX80",
+        crc32: 0x4C7FC597,
+        size: ZX80_ROM_SIZE,
+        machine: Machine::Zx80,
+        tape_traps: Some(ZX80_TAPE_TRAPS),
+        charset_addr: 0x0E00,
+    },
+];
+
+// Used when the ROM isn't in the table above, picked by size
+static UNKNOWN_ZX81_ROM: RomInfo = RomInfo {
+    name: "Unknown ROM (assuming ZX81 layout)",
+    crc32: 0,
+    size: ZX81_ROM_SIZE,
+    machine: Machine::Zx81,
+    tape_traps: Some(ZX81_TAPE_TRAPS),
+    charset_addr: 0x1E00,
+};
+
+static UNKNOWN_ZX80_ROM: RomInfo = RomInfo {
+    name: "Unknown ROM (assuming ZX80 layout)",
+    crc32: 0,
+    size: ZX80_ROM_SIZE,
+    machine: Machine::Zx80,
+    tape_traps: None,
+    charset_addr: 0x0E00,
+};
+
+impl RomInfo {
+    pub fn is_known(&self) -> bool {
+        self.crc32 != 0
+    }
+}
+
+pub fn identify_rom(data: &[u8]) -> Option<&'static RomInfo> {
+    let crc = crc32(data);
+    KNOWN_ROMS
+        .iter()
+        .find(|info| info.crc32 == crc && info.size == data.len())
+}
+
+// Identify a ROM, falling back to a best guess from its size
+pub fn rom_info(data: &[u8]) -> &'static RomInfo {
+    match identify_rom(data) {
+        Some(info) => info,
+        None if data.len() == ZX80_ROM_SIZE => &UNKNOWN_ZX80_ROM,
+        None => &UNKNOWN_ZX81_ROM,
+    }
+}
+
+// Standard CRC-32 (IEEE 802.3, reflected, as used by zip and MAME)
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB88320 & mask);
+        }
+    }
+    !crc
+}
 
 pub fn load_rom(rom_path: &str) -> Result<Vec<u8>, String> {
     let rom_data = fs::read(rom_path).map_err(|e| format!("Failed to read ROM: {}", e))?;
 
-    // Verify the size of the ROM
-    if rom_data.len() != ZX81_ROM_SIZE {
+    // Verify the size of the ROM (8K for the ZX81 family, 4K for the ZX80)
+    if rom_data.len() != ZX81_ROM_SIZE && rom_data.len() != ZX80_ROM_SIZE {
         return Err(format!(
-            "ROM is not expected size: Expected {}, got {}",
+            "ROM is not expected size: Expected {} or {}, got {}",
             ZX81_ROM_SIZE,
+            ZX80_ROM_SIZE,
             rom_data.len()
         ));
     }
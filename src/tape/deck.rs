@@ -0,0 +1,407 @@
+use std::path::PathBuf;
+
+use super::{PSysvars, Tape, TapeProgram};
+use crate::machine::CLOCK_HZ;
+
+// Auto-play watches how hard the machine is polling the EAR bit. A loader
+// samples it thousands of times a frame, the keyboard scan only a handful.
+const LISTEN_WINDOW_T: u64 = CLOCK_HZ / 50;
+const LISTEN_READS: u32 = 200;
+// How long the loader has to stop listening before we call the load finished
+const QUIET_STOP_T: u64 = CLOCK_HZ / 4;
+// Events kept for the frontend, the oldest go once it stops taking them
+const MAX_EVENTS: usize = 64;
+
+// Things the deck and the LOAD/SAVE traps report, for the frontend to show
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TapeEvent {
+    Playing,
+    Stopped,
+    Ended, // Played out to the end
+    Ejected {
+        queued: usize,
+    },
+    Inserted {
+        tape: usize,
+        of: usize,
+    }, // Counting from 1
+    AutoStarted, // A loader started listening
+    AutoStopped {
+        counter: String,
+    }, // The loader stopped listening
+    LoadRequested {
+        name: Option<String>,
+    }, // None for LOAD "" and on the ZX80
+    Loaded {
+        name: String,
+        bytes: usize,
+        sysvars: Option<PSysvars>,
+    }, // ZX80 programs have no name and none of the .p system variables
+    LoadFailed {
+        name: String,
+        error: String,
+    },
+    NoTape,
+    NotFound,
+    Saved {
+        path: PathBuf,
+        bytes: usize,
+    },
+    SaveFailed {
+        error: String,
+    },
+}
+
+// A cassette player with a stack of tapes beside it
+pub struct TapeDeck {
+    tapes: Vec<Tape>,
+    current: usize, // Tape in the deck, the rest wait their turn
+    auto_play: bool,
+    auto_started: bool, // Whether auto-play started the current run, so may stop it
+    listening: bool,
+    window_start: u64,
+    window_reads: u32,
+    quiet_since: u64,
+    events: Vec<TapeEvent>,
+}
+
+impl Default for TapeDeck {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TapeDeck {
+    pub fn new() -> Self {
+        Self {
+            tapes: Vec::new(),
+            current: 0,
+            auto_play: true,
+            auto_started: false,
+            listening: false,
+            window_start: 0,
+            window_reads: 0,
+            quiet_since: 0,
+            events: Vec::new(),
+        }
+    }
+
+    // Add a tape to the end of the queue, it goes straight in if the deck is empty
+    pub fn insert(&mut self, tape: Tape) {
+        self.tapes.push(tape);
+    }
+
+    // Take out the current tape and put the next one in
+    pub fn eject(&mut self) -> Option<Tape> {
+        if self.current >= self.tapes.len() {
+            return None;
+        }
+        self.auto_started = false;
+        let tape = self.tapes.remove(self.current);
+        self.report(TapeEvent::Ejected {
+            queued: self.queued(),
+        });
+        Some(tape)
+    }
+
+    // What has happened since the last call
+    pub fn take_events(&mut self) -> Vec<TapeEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    pub fn report(&mut self, event: TapeEvent) {
+        if self.events.len() == MAX_EVENTS {
+            self.events.remove(0);
+        }
+        self.events.push(event);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.current >= self.tapes.len()
+    }
+
+    // Tapes waiting after the current one
+    pub fn queued(&self) -> usize {
+        self.tapes.len().saturating_sub(self.current + 1)
+    }
+
+    pub fn tape(&self) -> Option<&Tape> {
+        self.tapes.get(self.current)
+    }
+
+    pub fn tape_mut(&mut self) -> Option<&mut Tape> {
+        self.tapes.get_mut(self.current)
+    }
+
+    pub fn tapes(&self) -> &[Tape] {
+        &self.tapes
+    }
+
+    pub fn set_auto_play(&mut self, auto_play: bool) {
+        self.auto_play = auto_play;
+    }
+
+    pub fn auto_play(&self) -> bool {
+        self.auto_play
+    }
+
+    pub fn play(&mut self) {
+        self.auto_started = false;
+        let was_playing = self.is_playing();
+        if let Some(tape) = self.tape_mut() {
+            tape.start_playing();
+        }
+        if !was_playing && self.is_playing() {
+            self.report(TapeEvent::Playing);
+        }
+    }
+
+    pub fn stop(&mut self) {
+        self.auto_started = false;
+        if let Some(tape) = self.tape_mut()
+            && tape.is_playing()
+        {
+            tape.stop();
+            self.report(TapeEvent::Stopped);
+        }
+    }
+
+    pub fn rewind(&mut self) {
+        if let Some(tape) = self.tape_mut() {
+            tape.rewind();
+        }
+    }
+
+    pub fn fast_forward(&mut self) {
+        if let Some(tape) = self.tape_mut() {
+            tape.fast_forward();
+        }
+    }
+
+    pub fn previous_block(&mut self) {
+        if let Some(tape) = self.tape_mut() {
+            tape.previous_block();
+        }
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.tape().is_some_and(|tape| tape.is_playing())
+    }
+
+    // Whether the machine looks like it's waiting for a tape signal
+    pub fn is_listening(&self) -> bool {
+        self.listening
+    }
+
+    // What the EAR socket hears right now
+    pub fn ear_level(&self) -> bool {
+        self.tape()
+            .is_some_and(|tape| tape.is_playing() && tape.get_level())
+    }
+
+    pub fn position_secs(&self) -> f64 {
+        self.tape().map_or(0.0, |tape| tape.position_secs())
+    }
+
+    // (current block, number of blocks) on the tape in the deck
+    pub fn position_blocks(&self) -> (usize, usize) {
+        self.tape()
+            .map_or((0, 0), |tape| (tape.current_block(), tape.block_count()))
+    }
+
+    // Counter display, e.g. "tape 1/2 block 2/3 0:14.2"
+    pub fn counter(&self) -> String {
+        let secs = self.position_secs();
+        let (block, blocks) = self.position_blocks();
+        format!(
+            "tape {}/{} block {}/{} {}:{:04.1}",
+            (self.current + 1).min(self.tapes.len()),
+            self.tapes.len(),
+            (block + 1).min(blocks),
+            blocks,
+            (secs / 60.0) as u64,
+            secs % 60.0
+        )
+    }
+
+    pub fn set_clock_hz(&mut self, clock_hz: u64) {
+        for tape in self.tapes.iter_mut() {
+            tape.set_clock_hz(clock_hz);
+        }
+    }
+
+    // Find a program for a trapped LOAD, moving on through the queue when the
+    // current tape doesn't have it
+    pub fn take_program(&mut self, name: Option<&str>) -> Option<&TapeProgram> {
+        let index = (self.current..self.tapes.len()).find(|&i| self.tapes[i].has_program(name))?;
+        if index != self.current {
+            self.current = index;
+            self.report(TapeEvent::Inserted {
+                tape: index + 1,
+                of: self.tapes.len(),
+            });
+        }
+        self.tapes[index].take_program(name)
+    }
+
+    // Run the tape on by `cycles`, with `ear_reads` reads of the EAR bit in that time
+    pub fn advance(&mut self, now: u64, cycles: u64, ear_reads: u32) {
+        self.watch_listening(now, ear_reads);
+
+        if self.auto_play && self.listening && !self.is_playing() {
+            let has_signal = self.tape().is_some_and(|tape| !tape.is_finished());
+            if has_signal {
+                self.report(TapeEvent::AutoStarted);
+                self.play();
+                self.auto_started = true;
+            }
+        }
+        if self.auto_started
+            && self.is_playing()
+            && !self.listening
+            && now - self.quiet_since >= QUIET_STOP_T
+        {
+            self.report(TapeEvent::AutoStopped {
+                counter: self.counter(),
+            });
+            self.stop();
+        }
+
+        let Some(tape) = self.tape_mut() else {
+            return;
+        };
+        let was_playing = tape.is_playing();
+        tape.advance(cycles);
+        let ended = was_playing && !tape.is_playing();
+        let finished = tape.is_finished();
+        if ended {
+            self.report(TapeEvent::Ended);
+        }
+
+        // Once a tape has played out the next one in the queue goes in
+        if finished && self.current + 1 < self.tapes.len() {
+            self.current += 1;
+            self.auto_started = false;
+            self.report(TapeEvent::Inserted {
+                tape: self.current + 1,
+                of: self.tapes.len(),
+            });
+        }
+    }
+
+    fn watch_listening(&mut self, now: u64, ear_reads: u32) {
+        self.window_reads += ear_reads;
+        if now - self.window_start < LISTEN_WINDOW_T {
+            return;
+        }
+        let listening = self.window_reads >= LISTEN_READS;
+        if self.listening && !listening {
+            self.quiet_since = now;
+        }
+        self.listening = listening;
+        self.window_start = now;
+        self.window_reads = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::TapeFormat;
+    use super::super::fixtures::p_program;
+    use super::*;
+
+    const STEP_T: u64 = LISTEN_WINDOW_T / 20;
+
+    fn deck_with_tapes(count: usize) -> TapeDeck {
+        let mut deck = TapeDeck::new();
+        for _ in 0..count {
+            deck.insert(Tape::from_programs(vec![p_program("T")], TapeFormat::Zx81));
+        }
+        deck
+    }
+
+    // Run the deck on for `t`, with the EAR bit read `reads` times every 1/50s
+    fn drive(deck: &mut TapeDeck, now: &mut u64, t: u64, reads: u32) {
+        let end = *now + t;
+        while *now < end {
+            deck.advance(*now, STEP_T, reads / 20);
+            *now += STEP_T;
+        }
+    }
+
+    #[test]
+    fn auto_play_starts_once_a_loader_listens() {
+        let mut deck = deck_with_tapes(1);
+        let mut now = 0;
+
+        // The keyboard scan alone never starts it
+        drive(&mut deck, &mut now, CLOCK_HZ, LISTEN_READS - 20);
+        assert!(!deck.is_playing());
+
+        drive(&mut deck, &mut now, 2 * LISTEN_WINDOW_T, LISTEN_READS);
+        assert!(deck.is_listening());
+        assert!(deck.is_playing());
+        assert_eq!(
+            deck.take_events(),
+            vec![TapeEvent::AutoStarted, TapeEvent::Playing]
+        );
+    }
+
+    #[test]
+    fn auto_play_off_leaves_the_tape_alone() {
+        let mut deck = deck_with_tapes(1);
+        deck.set_auto_play(false);
+        let mut now = 0;
+        drive(&mut deck, &mut now, CLOCK_HZ, LISTEN_READS);
+        assert!(deck.is_listening());
+        assert!(!deck.is_playing());
+    }
+
+    #[test]
+    fn auto_stops_after_a_quarter_second_of_quiet() {
+        let mut deck = deck_with_tapes(1);
+        let mut now = 0;
+        drive(&mut deck, &mut now, 2 * LISTEN_WINDOW_T, LISTEN_READS);
+        assert!(deck.is_playing());
+        deck.take_events();
+
+        // Quiet is counted from the first window the loader isn't heard in
+        drive(&mut deck, &mut now, QUIET_STOP_T, 0);
+        assert!(deck.is_playing());
+        drive(&mut deck, &mut now, 2 * LISTEN_WINDOW_T, 0);
+        assert!(!deck.is_playing());
+
+        let events = deck.take_events();
+        assert!(matches!(events[0], TapeEvent::AutoStopped { .. }));
+        assert_eq!(events[1..], [TapeEvent::Stopped]);
+    }
+
+    #[test]
+    fn a_tape_played_by_hand_isnt_auto_stopped() {
+        let mut deck = deck_with_tapes(1);
+        deck.play();
+        let mut now = 0;
+        drive(&mut deck, &mut now, CLOCK_HZ / 2, 0);
+        assert!(deck.is_playing());
+    }
+
+    #[test]
+    fn next_tape_goes_in_once_one_finishes() {
+        let mut deck = deck_with_tapes(2);
+        deck.play();
+        let duration = deck.tape().unwrap().duration_t();
+        deck.advance(0, duration + 1, 0);
+
+        assert_eq!(
+            deck.take_events(),
+            vec![
+                TapeEvent::Playing,
+                TapeEvent::Ended,
+                TapeEvent::Inserted { tape: 2, of: 2 }
+            ]
+        );
+        assert_eq!(deck.queued(), 0);
+        assert!(!deck.is_playing());
+        assert!(!deck.tape().unwrap().is_finished());
+    }
+}
@@ -0,0 +1,174 @@
+use std::f64::consts::TAU;
+use std::str::FromStr;
+
+// Ways to spoil a tape signal, to see how a loader copes with a worn cassette
+// Everything is off by default. The same seed always spoils a tape the same way,
+// whichever block playback starts from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Degradation {
+    pub seed: u64,
+    pub jitter: f64,       // Random error on every pulse, as a fraction of its length
+    pub wow: f64,          // Slow speed drift, as a fraction of normal speed
+    pub wow_hz: f64,       // How often the slow drift comes round
+    pub flutter: f64,      // Fast speed wobble, as a fraction of normal speed
+    pub flutter_hz: f64,   // How often the fast wobble comes round
+    pub dropouts: f64,     // Dropouts per second, where the signal vanishes
+    pub dropout_secs: f64, // How long each dropout lasts
+    pub inverted: bool,    // Signal upside down, as from some tape decks
+    pub spikes: f64,       // Noise spikes per second
+    pub spike_secs: f64,   // How long each spike lasts
+}
+
+impl Default for Degradation {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            jitter: 0.0,
+            wow: 0.0,
+            wow_hz: 0.5,
+            flutter: 0.0,
+            flutter_hz: 10.0,
+            dropouts: 0.0,
+            dropout_secs: 0.01,
+            inverted: false,
+            spikes: 0.0,
+            spike_secs: 0.0001,
+        }
+    }
+}
+
+// From a list like "jitter=0.05,wow=0.02,dropouts=0.5,invert,seed=7"
+impl FromStr for Degradation {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let mut degradation = Degradation::default();
+        for item in spec
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+        {
+            if item == "invert" {
+                degradation.inverted = true;
+                continue;
+            }
+            let (name, value) = item
+                .split_once('=')
+                .ok_or_else(|| format!("Expected name=value, got \"{}\"", item))?;
+            if name == "seed" {
+                degradation.seed = value
+                    .parse()
+                    .map_err(|_| format!("Invalid seed: {}", value))?;
+                continue;
+            }
+
+            let value: f64 = value
+                .parse()
+                .ok()
+                .filter(|value: &f64| value.is_finite() && *value >= 0.0)
+                .ok_or_else(|| format!("Invalid value for {}: {}", name, value))?;
+            match name {
+                "jitter" => degradation.jitter = value,
+                "wow" => degradation.wow = value,
+                "wow-hz" => degradation.wow_hz = value,
+                "flutter" => degradation.flutter = value,
+                "flutter-hz" => degradation.flutter_hz = value,
+                "dropouts" => degradation.dropouts = value,
+                "dropout-ms" => degradation.dropout_secs = value / 1000.0,
+                "spikes" => degradation.spikes = value,
+                "spike-us" => degradation.spike_secs = value / 1_000_000.0,
+                _ => return Err(format!("Unknown tape degradation: {}", name)),
+            }
+        }
+        Ok(degradation)
+    }
+}
+
+// Applies a Degradation to pulses as they come off the tape
+pub struct Degrader {
+    settings: Degradation,
+    rng: u64,
+    pending: Vec<(bool, u64)>, // The rest of a pulse a spike was put in, last first
+    dropout_left: u64,         // Source T-states of dropout still to come
+}
+
+impl Degrader {
+    pub fn new(settings: Degradation) -> Self {
+        Self {
+            settings,
+            rng: 0,
+            pending: Vec::new(),
+            dropout_left: 0,
+        }
+    }
+
+    pub fn settings(&self) -> &Degradation {
+        &self.settings
+    }
+
+    // Start again at the top of a block, so where the damage falls doesn't depend on
+    // how playback got there
+    pub fn restart(&mut self, block: usize) {
+        self.rng = self.settings.seed ^ (block as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        self.pending.clear();
+        self.dropout_left = 0;
+    }
+
+    // The next pulse as it comes out spoiled, taking a clean one from `clean` when
+    // needed. `t` is how far into the tape we are, in T-states of a `clock_hz` clock.
+    pub fn next_pulse(
+        &mut self,
+        t: u64,
+        clock_hz: u64,
+        clean: impl FnOnce() -> Option<(bool, u64)>,
+    ) -> Option<(bool, u64)> {
+        let (level, length) = match self.pending.pop() {
+            Some(pulse) => pulse,
+            None => self.spoil(t, clock_hz, clean()?),
+        };
+        Some((level ^ self.settings.inverted, length))
+    }
+
+    fn spoil(&mut self, t: u64, clock_hz: u64, (mut level, length): (bool, u64)) -> (bool, u64) {
+        let jitter = self.random() * 2.0 - 1.0;
+        let settings = &self.settings;
+        let secs = t as f64 / clock_hz as f64;
+
+        // A slow tape makes every pulse longer
+        let speed = 1.0
+            + settings.wow * (TAU * settings.wow_hz * secs).sin()
+            + settings.flutter * (TAU * settings.flutter_hz * secs).sin();
+        let length = (length as f64 / speed.max(0.01) * (1.0 + settings.jitter * jitter)) as u64;
+        let length_secs = length as f64 / clock_hz as f64;
+
+        // During a dropout the tape is silent, whatever was recorded on it
+        let dropout_t = (settings.dropout_secs * clock_hz as f64) as u64;
+        if self.dropout_left == 0 && self.random() < self.settings.dropouts * length_secs {
+            self.dropout_left = dropout_t;
+        }
+        if self.dropout_left > 0 {
+            self.dropout_left = self.dropout_left.saturating_sub(length);
+            level = false;
+        }
+
+        // A spike flips the level for a moment in the middle of the pulse
+        let spike = (self.settings.spike_secs * clock_hz as f64).max(1.0) as u64;
+        if length > spike * 2 && self.random() < self.settings.spikes * length_secs {
+            let before = (length - spike) / 2;
+            self.pending.push((level, length - spike - before));
+            self.pending.push((!level, spike));
+            return (level, before);
+        }
+        (level, length)
+    }
+
+    // SplitMix64, 0.0 up to (not including) 1.0
+    fn random(&mut self) -> f64 {
+        self.rng = self.rng.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.rng;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        (z >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
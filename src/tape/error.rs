@@ -0,0 +1,51 @@
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+// Why a tape couldn't be loaded
+#[derive(Debug)]
+pub enum TapeError {
+    Io(io::Error),
+    UnknownFormat(String), // Not something we can read, and what we made of it
+    Truncated(String),     // Stops before its own headers say it should
+    Corrupt(String),       // Recognised, but the contents don't add up
+}
+
+impl TapeError {
+    // The same error, saying which file it came from
+    pub fn in_file(self, path: &Path) -> Self {
+        let context = |what: String| format!("{}: {}", path.display(), what);
+        match self {
+            TapeError::Io(e) => TapeError::Io(io::Error::new(e.kind(), context(e.to_string()))),
+            TapeError::UnknownFormat(what) => TapeError::UnknownFormat(context(what)),
+            TapeError::Truncated(what) => TapeError::Truncated(context(what)),
+            TapeError::Corrupt(what) => TapeError::Corrupt(context(what)),
+        }
+    }
+}
+
+impl fmt::Display for TapeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TapeError::Io(e) => write!(f, "I/O error: {}", e),
+            TapeError::UnknownFormat(what) => write!(f, "Unknown tape format: {}", what),
+            TapeError::Truncated(what) => write!(f, "Truncated tape: {}", what),
+            TapeError::Corrupt(what) => write!(f, "Corrupt tape: {}", what),
+        }
+    }
+}
+
+impl std::error::Error for TapeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TapeError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for TapeError {
+    fn from(e: io::Error) -> Self {
+        TapeError::Io(e)
+    }
+}
@@ -0,0 +1,570 @@
+use super::{TapeError, TapeProgram};
+use crate::charset;
+
+// ZX81 programs are saved from VERSN, the first byte after the fixed system variables
+const P_PROGRAM_START: usize = 0x4009;
+const P_E_LINE_OFFSET: usize = 0x4014 - P_PROGRAM_START;
+const P_SYSVARS_END: usize = 0x407D - P_PROGRAM_START; // First byte of the BASIC program
+const MAX_NAME_LEN: usize = 127;
+
+// ZX80 programs are saved from the start of the system variables
+const O_PROGRAM_START: usize = 0x4000;
+const O_E_LINE_OFFSET: usize = 0x400A - O_PROGRAM_START;
+
+// The system variables a .p file carries, as the ROM finds them once it's loaded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PSysvars {
+    pub d_file: u16, // Display file, straight after the BASIC program
+    pub df_cc: u16,  // Print position in the display file
+    pub vars: u16,   // BASIC variables, after the display file
+    pub e_line: u16, // Edit line, the end of what was saved
+    pub nxtlin: u16, // Next line to run, anywhere outside the program when there isn't one
+}
+
+impl PSysvars {
+    // The line NXTLIN says to run on from, if it points into the BASIC program
+    pub fn autostart(&self) -> Option<u16> {
+        let program_start = (P_PROGRAM_START + P_SYSVARS_END) as u16;
+        (program_start..self.d_file)
+            .contains(&self.nxtlin)
+            .then_some(self.nxtlin)
+    }
+
+    // Read the saved system variables and check they describe the data they came with
+    pub fn read(data: &[u8]) -> Result<Self, TapeError> {
+        if data.len() < P_SYSVARS_END {
+            return Err(TapeError::Truncated(format!(
+                "program is {} bytes, too short for its system variables",
+                data.len()
+            )));
+        }
+        if data[0] != 0x00 {
+            return Err(TapeError::Corrupt(format!(
+                "VERSN is {:02X}, not a ZX81 program",
+                data[0]
+            )));
+        }
+
+        let sysvar = |addr: usize| read_word(data, addr - P_PROGRAM_START).unwrap_or(0);
+        let sysvars = PSysvars {
+            d_file: sysvar(0x400C),
+            df_cc: sysvar(0x400E),
+            vars: sysvar(0x4010),
+            e_line: sysvar(0x4014),
+            nxtlin: sysvar(0x4029),
+        };
+        let at = |addr: u16| {
+            data.get((addr as usize).wrapping_sub(P_PROGRAM_START))
+                .copied()
+        };
+        let program_start = (P_PROGRAM_START + P_SYSVARS_END) as u16;
+
+        let end = (P_PROGRAM_START + data.len()) as u16;
+        if sysvars.e_line > end {
+            return Err(TapeError::Truncated(format!(
+                "E_LINE is {:04X} but the data ends at {:04X}",
+                sysvars.e_line, end
+            )));
+        }
+        if sysvars.e_line < end {
+            return Err(TapeError::Corrupt(format!(
+                "E_LINE is {:04X} but the data runs on to {:04X}",
+                sysvars.e_line, end
+            )));
+        }
+        if sysvars.d_file < program_start || at(sysvars.d_file) != Some(0x76) {
+            return Err(TapeError::Corrupt(format!(
+                "D_FILE ({:04X}) doesn't point at a display file",
+                sysvars.d_file
+            )));
+        }
+        if sysvars.vars <= sysvars.d_file || sysvars.vars >= sysvars.e_line {
+            return Err(TapeError::Corrupt(format!(
+                "VARS ({:04X}) isn't between D_FILE ({:04X}) and E_LINE ({:04X})",
+                sysvars.vars, sysvars.d_file, sysvars.e_line
+            )));
+        }
+        if at(sysvars.e_line - 1) != Some(0x80) {
+            return Err(TapeError::Corrupt(
+                "variables area doesn't end with its 80 marker".to_string(),
+            ));
+        }
+        if !(sysvars.d_file..=sysvars.vars).contains(&sysvars.df_cc) {
+            return Err(TapeError::Corrupt(format!(
+                "DF_CC ({:04X}) is outside the display file",
+                sysvars.df_cc
+            )));
+        }
+        Ok(sysvars)
+    }
+}
+
+// File layouts we can read and write
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TapeFileFormat {
+    P,   // A single ZX81 program, no name
+    P81, // ZX81 programs back to back, each preceded by its name (.p81 and .81)
+    O,   // A single ZX80 program
+}
+
+impl TapeFileFormat {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TapeFileFormat::P => ".p",
+            TapeFileFormat::P81 => ".p81",
+            TapeFileFormat::O => ".o",
+        }
+    }
+}
+
+// Work out what a file holds from its bytes, the extension only breaks ties
+pub fn detect_format(bytes: &[u8], extension: &str) -> Option<TapeFileFormat> {
+    let is_p = p_program_len(bytes).is_some();
+    let is_p81 = name_len(bytes).is_some_and(|len| p_program_len(&bytes[len..]).is_some());
+    let is_o = o_program_len(bytes).is_some();
+
+    let preferred = format_for_extension(extension);
+    let candidates = [
+        (TapeFileFormat::P, is_p),
+        (TapeFileFormat::P81, is_p81),
+        (TapeFileFormat::O, is_o),
+    ];
+    if let Some(preferred) = preferred
+        && candidates.contains(&(preferred, true))
+    {
+        return Some(preferred);
+    }
+
+    candidates
+        .iter()
+        .find(|(_, matches)| *matches)
+        .map(|(format, _)| *format)
+}
+
+// Why detect_format found nothing, as closely as the extension lets us say
+pub fn undetected(bytes: &[u8], extension: &str) -> TapeError {
+    let unknown = || {
+        TapeError::UnknownFormat(format!(
+            "{} bytes that aren't a .p, .p81/.81, .o, .tzx or .wav file",
+            bytes.len()
+        ))
+    };
+    match format_for_extension(extension) {
+        Some(format) => parse(bytes, format, "").err().unwrap_or_else(unknown),
+        // With no extension to go on, a VERSN of 0 is the best sign of a cut off .p file
+        None if bytes.first() == Some(&0x00) => match p_error(bytes, ".p file") {
+            e @ TapeError::Truncated(_) => e,
+            _ => unknown(),
+        },
+        None => unknown(),
+    }
+}
+
+fn format_for_extension(extension: &str) -> Option<TapeFileFormat> {
+    match extension.to_ascii_lowercase().as_str() {
+        "p81" | "81" => Some(TapeFileFormat::P81),
+        "o" | "80" => Some(TapeFileFormat::O),
+        "p" => Some(TapeFileFormat::P),
+        _ => None,
+    }
+}
+
+// Length of the .p program at the start of `bytes`, from its saved E_LINE
+pub fn p_program_len(bytes: &[u8]) -> Option<usize> {
+    if bytes.len() < P_SYSVARS_END || bytes[0] != 0x00 {
+        // VERSN is always 0 on a ZX81
+        return None;
+    }
+
+    let e_line = read_word(bytes, P_E_LINE_OFFSET)? as usize;
+    let len = e_line.checked_sub(P_PROGRAM_START)?;
+    if len < P_SYSVARS_END || len > bytes.len() {
+        return None;
+    }
+    Some(len)
+}
+
+fn o_program_len(bytes: &[u8]) -> Option<usize> {
+    let e_line = read_word(bytes, O_E_LINE_OFFSET)? as usize;
+    let len = e_line.checked_sub(O_PROGRAM_START)?;
+    if len <= O_E_LINE_OFFSET + 1 || len > bytes.len() {
+        return None;
+    }
+    Some(len)
+}
+
+// Length of a tape name (ending in an inverse character) at the start of `bytes`
+fn name_len(bytes: &[u8]) -> Option<usize> {
+    for (i, &byte) in bytes.iter().take(MAX_NAME_LEN).enumerate() {
+        // Anything outside the character set means this isn't a name
+        charset::to_char(byte & !charset::INVERSE)?;
+        if byte & charset::INVERSE != 0 {
+            return Some(i + 1);
+        }
+    }
+    None
+}
+
+fn read_word(bytes: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes([
+        *bytes.get(offset)?,
+        *bytes.get(offset + 1)?,
+    ]))
+}
+
+// Split a file into programs. `default_name` names programs that don't carry one.
+// Anything after a program's E_LINE is ignored.
+pub fn parse(
+    bytes: &[u8],
+    format: TapeFileFormat,
+    default_name: &str,
+) -> Result<Vec<TapeProgram>, TapeError> {
+    match format {
+        TapeFileFormat::P => parse_p(bytes, default_name).map(|program| vec![program]),
+        TapeFileFormat::P81 => parse_p81(bytes),
+        TapeFileFormat::O => parse_o(bytes).map(|program| vec![program]),
+    }
+}
+
+pub fn parse_p(bytes: &[u8], name: &str) -> Result<TapeProgram, TapeError> {
+    let len = p_program_len(bytes).ok_or_else(|| p_error(bytes, ".p file"))?;
+    Ok(TapeProgram {
+        name: charset::encode_name(name),
+        data: bytes[..len].to_vec(),
+    })
+}
+
+pub fn parse_p81(bytes: &[u8]) -> Result<Vec<TapeProgram>, TapeError> {
+    let mut programs = Vec::new();
+    let mut offset = 0;
+
+    while offset < bytes.len() {
+        let rest = &bytes[offset..];
+        let program = parse_named_program(rest).ok_or_else(|| match name_len(rest) {
+            Some(len) => p_error(&rest[len..], &format!("program at offset {}", offset)),
+            None if rest.len() < MAX_NAME_LEN
+                && rest.iter().all(|&b| b & charset::INVERSE == 0) =>
+            {
+                TapeError::Truncated(format!("name at offset {} has no end", offset))
+            }
+            None => TapeError::Corrupt(format!("bad program name at offset {}", offset)),
+        })?;
+        offset += program.name.len() + program.data.len();
+        programs.push(program);
+    }
+
+    if programs.is_empty() {
+        return Err(TapeError::Truncated("empty .p81 file".to_string()));
+    }
+    Ok(programs)
+}
+
+// A name followed by .p data, as a .p81 entry or a ZX81 tape block holds them
+pub(super) fn parse_named_program(bytes: &[u8]) -> Option<TapeProgram> {
+    let name_len = name_len(bytes)?;
+    let len = p_program_len(&bytes[name_len..])?;
+    Some(TapeProgram {
+        name: bytes[..name_len].to_vec(),
+        data: bytes[name_len..name_len + len].to_vec(),
+    })
+}
+
+pub fn parse_o(bytes: &[u8]) -> Result<TapeProgram, TapeError> {
+    let len = o_program_len(bytes).ok_or_else(|| {
+        match read_word(bytes, O_E_LINE_OFFSET).map(|e_line| e_line as usize) {
+            Some(e_line) if e_line > O_PROGRAM_START + bytes.len() => {
+                TapeError::Truncated(format!(
+                    ".o file is {} bytes, E_LINE says {}",
+                    bytes.len(),
+                    e_line - O_PROGRAM_START
+                ))
+            }
+            Some(_) => TapeError::Corrupt(".o file has a bad E_LINE".to_string()),
+            None => TapeError::Truncated(format!(".o file is only {} bytes", bytes.len())),
+        }
+    })?;
+    Ok(TapeProgram {
+        name: Vec::new(),
+        data: bytes[..len].to_vec(),
+    })
+}
+
+// Why `bytes` isn't a .p program
+fn p_error(bytes: &[u8], what: &str) -> TapeError {
+    if bytes.len() < P_SYSVARS_END {
+        return TapeError::Truncated(format!("{} is only {} bytes", what, bytes.len()));
+    }
+    if bytes[0] != 0x00 {
+        return TapeError::Corrupt(format!("{} has VERSN {:02X}", what, bytes[0]));
+    }
+    match read_word(bytes, P_E_LINE_OFFSET).map(|e_line| e_line as usize) {
+        Some(e_line) if e_line > P_PROGRAM_START + bytes.len() => TapeError::Truncated(format!(
+            "{} is {} bytes, E_LINE says {}",
+            what,
+            bytes.len(),
+            e_line - P_PROGRAM_START
+        )),
+        _ => TapeError::Corrupt(format!("{} has a bad E_LINE", what)),
+    }
+}
+
+// A .p file holds just the program bytes
+pub fn write_p(program: &TapeProgram) -> Vec<u8> {
+    program.data.clone()
+}
+
+// .p81 (and .81) files are each program's name followed by its bytes
+pub fn write_p81(programs: &[TapeProgram]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for program in programs {
+        bytes.extend_from_slice(&program.name);
+        bytes.extend_from_slice(&program.data);
+    }
+    bytes
+}
+
+// Program images for the tape tests
+#[cfg(test)]
+pub(crate) mod fixtures {
+    use super::*;
+
+    pub const D_FILE: u16 = 0x407D; // Straight after the system variables, no program
+    pub const VARS: u16 = D_FILE + 25; // After a collapsed display file
+
+    // An empty ZX81 program as SAVE writes it, padded with `extra` trailing bytes
+    pub fn p_image(extra: usize) -> Vec<u8> {
+        let mut data = vec![0; P_SYSVARS_END];
+        data.extend([0x76; 25]);
+        data.push(0x80);
+        let e_line = (P_PROGRAM_START + data.len()) as u16;
+        set_word(&mut data, 0x400C, D_FILE);
+        set_word(&mut data, 0x400E, D_FILE + 1);
+        set_word(&mut data, 0x4010, VARS);
+        set_word(&mut data, 0x4014, e_line);
+        set_word(&mut data, 0x4029, D_FILE);
+        data.resize(data.len() + extra, 0xFF);
+        data
+    }
+
+    // Set a system variable in a .p image
+    pub fn set_word(data: &mut [u8], addr: usize, value: u16) {
+        let offset = addr - P_PROGRAM_START;
+        data[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+    }
+
+    // A named program as a .p81 file holds it
+    pub fn p81_image(name: &str) -> Vec<u8> {
+        let mut bytes = charset::encode_name(name);
+        bytes.extend(p_image(0));
+        bytes
+    }
+
+    // A named program on its own, as a tape holds it
+    pub fn p_program(name: &str) -> TapeProgram {
+        TapeProgram {
+            name: charset::encode_name(name),
+            data: p_image(0),
+        }
+    }
+
+    // A ZX80 program: system variables from 0x4000 and a few program bytes
+    pub fn o_image() -> Vec<u8> {
+        let mut data = vec![0; 0x28 + 4];
+        let e_line = (O_PROGRAM_START + data.len()) as u16;
+        data[O_E_LINE_OFFSET..O_E_LINE_OFFSET + 2].copy_from_slice(&e_line.to_le_bytes());
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fixtures::*;
+    use super::*;
+
+    #[test]
+    fn detects_each_format_without_an_extension() {
+        assert_eq!(detect_format(&p_image(0), ""), Some(TapeFileFormat::P));
+        assert_eq!(
+            detect_format(&p81_image("HI"), ""),
+            Some(TapeFileFormat::P81)
+        );
+        assert_eq!(detect_format(&o_image(), ""), Some(TapeFileFormat::O));
+    }
+
+    #[test]
+    fn contents_win_over_a_wrong_extension() {
+        assert_eq!(detect_format(&p_image(0), "o"), Some(TapeFileFormat::P));
+        assert_eq!(
+            detect_format(&p81_image("HI"), "p"),
+            Some(TapeFileFormat::P81)
+        );
+        assert_eq!(detect_format(&o_image(), "p81"), Some(TapeFileFormat::O));
+    }
+
+    #[test]
+    fn extension_breaks_a_tie() {
+        // DEST's high byte and E_LINE's low byte read as a ZX80 E_LINE of 0x9700, so
+        // with enough trailing bytes this is a .o file as well as a .p one
+        let mut bytes = p_image(0);
+        set_word(&mut bytes, 0x4012, 0x0000);
+        let e_line = read_word(&bytes, P_E_LINE_OFFSET).unwrap();
+        let o_len = (u16::from_le_bytes([0x00, e_line as u8]) as usize) - O_PROGRAM_START;
+        bytes.resize(o_len, 0xFF);
+
+        assert_eq!(detect_format(&bytes, "p"), Some(TapeFileFormat::P));
+        assert_eq!(detect_format(&bytes, "o"), Some(TapeFileFormat::O));
+        assert_eq!(detect_format(&bytes, "80"), Some(TapeFileFormat::O));
+        assert_eq!(detect_format(&bytes, ""), Some(TapeFileFormat::P));
+    }
+
+    #[test]
+    fn extension_is_case_insensitive() {
+        assert_eq!(
+            detect_format(&p81_image("HI"), "P81"),
+            Some(TapeFileFormat::P81)
+        );
+        assert_eq!(
+            detect_format(&p81_image("HI"), "81"),
+            Some(TapeFileFormat::P81)
+        );
+    }
+
+    #[test]
+    fn nothing_detected_in_noise() {
+        assert_eq!(detect_format(&[0xFF; 8], "p"), None);
+        assert!(matches!(
+            undetected(&[0xFF; 8], ""),
+            TapeError::UnknownFormat(_)
+        ));
+    }
+
+    #[test]
+    fn undetected_says_truncated_for_a_cut_off_p_file() {
+        let bytes = &p_image(0)[..P_SYSVARS_END + 4];
+        assert!(matches!(undetected(bytes, ""), TapeError::Truncated(_)));
+        assert!(matches!(undetected(bytes, "p"), TapeError::Truncated(_)));
+    }
+
+    #[test]
+    fn undetected_says_corrupt_for_a_bad_versn() {
+        let mut bytes = p_image(0);
+        bytes[0] = 0x01;
+        assert!(matches!(undetected(&bytes, "p"), TapeError::Corrupt(_)));
+    }
+
+    #[test]
+    fn trailing_bytes_are_ignored() {
+        let programs = parse(&p_image(10), TapeFileFormat::P, "X").unwrap();
+        assert_eq!(programs[0].data, p_image(0));
+    }
+
+    #[test]
+    fn p81_round_trips() {
+        let mut bytes = p81_image("HI");
+        bytes.extend(p81_image("THERE"));
+        let programs = parse_p81(&bytes).unwrap();
+        assert_eq!(programs.len(), 2);
+        assert_eq!(programs[0].name, charset::encode_name("HI"));
+        assert_eq!(programs[1].name, charset::encode_name("THERE"));
+        assert_eq!(write_p81(&programs), bytes);
+    }
+
+    #[test]
+    fn p81_errors() {
+        assert!(matches!(parse_p81(&[]), Err(TapeError::Truncated(_))));
+        // A name that never ends in an inverse character
+        assert!(matches!(
+            parse_p81(&charset::encode_name("HI")[..1]),
+            Err(TapeError::Truncated(_))
+        ));
+        assert!(matches!(parse_p81(&[0xFF; 4]), Err(TapeError::Corrupt(_))));
+        let bytes = p81_image("HI");
+        assert!(matches!(
+            parse_p81(&bytes[..bytes.len() - 1]),
+            Err(TapeError::Truncated(_))
+        ));
+    }
+
+    #[test]
+    fn o_errors() {
+        assert!(matches!(parse_o(&[0; 4]), Err(TapeError::Truncated(_))));
+        let bytes = o_image();
+        assert!(matches!(
+            parse_o(&bytes[..bytes.len() - 1]),
+            Err(TapeError::Truncated(_))
+        ));
+        let mut bytes = o_image();
+        bytes[O_E_LINE_OFFSET..O_E_LINE_OFFSET + 2].copy_from_slice(&0x3000u16.to_le_bytes());
+        assert!(matches!(parse_o(&bytes), Err(TapeError::Corrupt(_))));
+    }
+
+    #[test]
+    fn reads_valid_sysvars() {
+        let sysvars = PSysvars::read(&p_image(0)).unwrap();
+        assert_eq!(sysvars.d_file, D_FILE);
+        assert_eq!(sysvars.vars, VARS);
+        assert_eq!(sysvars.e_line, VARS + 1);
+        assert_eq!(sysvars.autostart(), None);
+    }
+
+    #[test]
+    fn autostart_only_inside_the_program() {
+        let mut data = p_image(0);
+        // Move the display file up to make room for a program line
+        data.splice(
+            P_SYSVARS_END..P_SYSVARS_END,
+            [0x00, 0x0A, 0x02, 0x00, 0xEC, 0x76],
+        );
+        set_word(&mut data, 0x400C, D_FILE + 6);
+        set_word(&mut data, 0x400E, D_FILE + 7);
+        set_word(&mut data, 0x4010, VARS + 6);
+        set_word(&mut data, 0x4014, VARS + 7);
+        set_word(&mut data, 0x4029, D_FILE);
+        assert_eq!(PSysvars::read(&data).unwrap().autostart(), Some(D_FILE));
+    }
+
+    fn read_error(change: impl FnOnce(&mut Vec<u8>)) -> TapeError {
+        let mut data = p_image(0);
+        change(&mut data);
+        PSysvars::read(&data).unwrap_err()
+    }
+
+    #[test]
+    fn sysvar_errors() {
+        assert!(matches!(
+            read_error(|data| data.truncate(10)),
+            TapeError::Truncated(_)
+        ));
+        assert!(matches!(
+            read_error(|data| data[0] = 0x01),
+            TapeError::Corrupt(_)
+        ));
+        // E_LINE past the end of the data, and short of it
+        assert!(matches!(
+            read_error(|data| {
+                data.pop();
+            }),
+            TapeError::Truncated(_)
+        ));
+        assert!(matches!(
+            read_error(|data| data.push(0x80)),
+            TapeError::Corrupt(_)
+        ));
+        assert!(matches!(
+            read_error(|data| set_word(data, 0x400C, D_FILE + 30)),
+            TapeError::Corrupt(_)
+        ));
+        assert!(matches!(
+            read_error(|data| set_word(data, 0x4010, D_FILE)),
+            TapeError::Corrupt(_)
+        ));
+        assert!(matches!(
+            read_error(|data| *data.last_mut().unwrap() = 0x00),
+            TapeError::Corrupt(_)
+        ));
+        assert!(matches!(
+            read_error(|data| set_word(data, 0x400E, VARS + 1)),
+            TapeError::Corrupt(_)
+        ));
+    }
+}
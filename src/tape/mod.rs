@@ -1,109 +1,461 @@
 use std::fs;
-use std::process;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use crate::charset;
+use crate::machine::CLOCK_HZ;
+
+mod deck;
+mod degrade;
+mod error;
+mod formats;
+mod recorder;
+mod stream;
+pub mod tzx;
+pub mod wav;
+
+pub use deck::{TapeDeck, TapeEvent};
+pub use degrade::Degradation;
+pub use error::TapeError;
+#[cfg(test)]
+pub(crate) use formats::fixtures;
+pub use formats::{PSysvars, TapeFileFormat, detect_format, parse, parse_o, write_p, write_p81};
+pub use recorder::MicRecorder;
+pub use tzx::TzxInfo;
+
+use degrade::Degrader;
+use stream::{Block, Cursor, Pulses};
 
-const CLOCK_HZ: u64 = 3_250_000;
 const LEADER_SEC: u64 = 2; // Reduced from 5 for testing
 const LEADER_T: u64 = LEADER_SEC * CLOCK_HZ;
 const PULSE_HIGH_T: u64 = 488;
 const PULSE_LOW_T: u64 = 487;
 const SILENCE_BIT_T: u64 = 4225;
 const PAUSE_END_T: u64 = CLOCK_HZ;
+const BLOCK_GAP_T: u64 = CLOCK_HZ / 2; // Silence long enough to separate two blocks
+
+// Which machine's tape layout the data follows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TapeFormat {
+    Zx81, // .p: named, data saved from 0x4009
+    Zx80, // .o: no name, data saved from 0x4000
+}
+
+// One program on a tape
+pub struct TapeProgram {
+    pub name: Vec<u8>, // ZX81 characters, last one inverse (empty for ZX80 tapes)
+    pub data: Vec<u8>, // Raw .p/.o bytes
+}
+
+impl TapeProgram {
+    pub fn name(&self) -> String {
+        charset::decode_name(&self.name)
+    }
+}
 
 pub struct Tape {
-    pub data: Vec<u8>, // Raw .p bytes
-    pub pulses: Vec<(bool, u64)>,
-    pub playing: bool,
-    pub current_index: usize,
-    pub remaining: u64,
-    pub level: bool,
+    pub programs: Vec<TapeProgram>,
+    pub next_program: usize, // Where LOAD "" picks up from
+    pub format: TapeFormat,
+    pub clock_hz: u64,             // Clock the tape plays against
+    pub tzx_info: Option<TzxInfo>, // Archive info and text from a TZX file
+    source: String,                // What the tape was made from, e.g. ".p81 file"
+    blocks: Vec<Block>,
+    source_hz: u64,         // Clock the blocks' lengths are counted in
+    block_starts: Vec<u64>, // Where each block starts in source T-states, then the end
+    playing: bool,
+    block: usize,      // Block being played, blocks.len() once the tape has run out
+    cursor: Cursor,    // Next pulse in the block
+    pulse_start: u64,  // Source T-states before the current pulse
+    pulse_length: u64, // Current pulse in source T-states
+    remaining: u64,    // Playback T-states left of the current pulse
+    level: bool,
+    degrader: Option<Degrader>, // Spoils the signal as it plays, for testing loaders
 }
 
 impl Tape {
-    pub fn new(path: &str) -> Self {
-        let data = match load_p_file(path) {
-            Ok(data) => data,
-            Err(e) => {
-                eprintln!("ERROR: Error loading tape: {}", e);
-                process::exit(1);
-            }
+    // Load a single .p/.p81/.81/.o/.tzx/.wav file, or every ZX81 tape file in a directory.
+    // The file's stem names a .p program, its extension only breaks ties between layouts.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, TapeError> {
+        let path = path.as_ref();
+        if path.is_dir() {
+            let mut tape = Self::from_programs(load_directory(path)?, TapeFormat::Zx81);
+            tape.source = "directory".to_string();
+            return Ok(tape);
+        }
+        let extension = path.extension().unwrap_or_default().to_string_lossy();
+        Self::load(&fs::read(path)?, &file_stem(path), &extension)
+    }
+
+    // A tape image already in memory, its format worked out from the bytes alone
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, TapeError> {
+        Self::load(bytes, "", "")
+    }
+
+    pub fn from_reader(mut reader: impl Read) -> Result<Self, TapeError> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        Self::from_bytes(&bytes)
+    }
+
+    fn load(bytes: &[u8], name: &str, extension: &str) -> Result<Self, TapeError> {
+        if tzx::is_tzx(bytes) {
+            return tzx::parse(bytes).map(Self::from_tzx);
+        }
+        if wav::is_wav(bytes) {
+            return wav::read_wav(bytes).map(|audio| Self::from_wav(&audio));
+        }
+
+        let (programs, file_format) = parse_programs(bytes, name, extension)?;
+        let format = match file_format {
+            TapeFileFormat::O => TapeFormat::Zx80,
+            TapeFileFormat::P | TapeFileFormat::P81 => TapeFormat::Zx81,
         };
+        let mut tape = Self::from_programs(programs, format);
+        tape.source = format!("{} file", file_format.name());
+        Ok(tape)
+    }
+
+    pub fn from_programs(programs: Vec<TapeProgram>, format: TapeFormat) -> Self {
+        // The ZX80 doesn't save a name at all
+        let blocks = (0..programs.len())
+            .map(|index| Block::Program {
+                index,
+                with_name: format == TapeFormat::Zx81,
+                leader_t: LEADER_T,
+            })
+            .collect();
+        Self::with_blocks(programs, format, blocks, CLOCK_HZ, "programs".to_string())
+    }
 
-        let pulses = Self::generate_pulses(&data);
-        println!(
-            "Tape: {} bytes, {} pulses, (leader={} cycles)",
-            data.len(),
-            pulses.len(),
-            LEADER_T
+    // A TZX tape plays its own pulses, the programs decoded from it serve trapped LOADs
+    pub fn from_tzx(tzx: tzx::TzxTape) -> Self {
+        let source = format!("TZX v{}.{}", tzx.info.version.0, tzx.info.version.1);
+        let blocks = stream::split_recording(&tzx.pulses, BLOCK_GAP_T);
+        let mut tape = Self::with_blocks(tzx.programs, TapeFormat::Zx81, blocks, CLOCK_HZ, source);
+        tape.tzx_info = Some(tzx.info);
+        tape
+    }
+
+    // A recording plays as it was captured, after cleaning up the signal
+    // Whatever programs decode cleanly from it are there for trapped LOADs
+    pub fn from_wav(audio: &wav::WavAudio) -> Self {
+        let pulses = wav::condition(audio, CLOCK_HZ);
+        let programs = wav::decode_programs(&pulses, CLOCK_HZ);
+        let source = format!("WAV {}Hz", audio.sample_rate);
+        let blocks = stream::split_recording(&pulses, BLOCK_GAP_T);
+        Self::with_blocks(programs, TapeFormat::Zx81, blocks, CLOCK_HZ, source)
+    }
+
+    // A capture of the MIC line, e.g. from a SAVE through the genuine ROM routine
+    pub fn from_recording(pulses: Vec<(bool, u64)>, clock_hz: u64) -> Self {
+        let programs = wav::decode_programs(&pulses, clock_hz);
+        let source = "MIC recording".to_string();
+        let blocks = stream::split_recording(&pulses, BLOCK_GAP_T * clock_hz / CLOCK_HZ);
+        Self::with_blocks(programs, TapeFormat::Zx81, blocks, clock_hz, source)
+    }
+
+    // What's on the tape, for printing when it's loaded
+    pub fn summary(&self) -> Vec<String> {
+        let mut lines = vec![format!(
+            "Tape: {}, {} program(s), {} bytes, {:.1}s, {} block(s)",
+            self.source,
+            self.programs.len(),
+            self.programs.iter().map(|p| p.data.len()).sum::<usize>(),
+            self.duration_secs(),
+            self.blocks.len()
+        )];
+        if let Some(info) = &self.tzx_info {
+            for (field, value) in &info.archive_info {
+                lines.push(format!("  {}: {}", field, value));
+            }
+            for text in &info.texts {
+                lines.push(format!("  {}", text));
+            }
+        }
+        lines.extend(
+            listing(&self.programs)
+                .iter()
+                .map(|line| format!("  {}", line)),
         );
+        if let Some(info) = &self.tzx_info
+            && !info.unsupported.is_empty()
+        {
+            lines.push(format!(
+                "WARNING: {} unsupported TZX block(s) skipped, the tape may not load:",
+                info.unsupported.len()
+            ));
+            for block in &info.unsupported {
+                lines.push(format!(
+                    "  Block 0x{:02X} ({}) at offset {}",
+                    block.id,
+                    block.name(),
+                    block.offset
+                ));
+            }
+        }
+        lines
+    }
 
-        Self {
-            data,
-            pulses,
+    fn with_blocks(
+        programs: Vec<TapeProgram>,
+        format: TapeFormat,
+        blocks: Vec<Block>,
+        source_hz: u64,
+        source: String,
+    ) -> Self {
+        let mut block_starts = vec![0];
+        for block in &blocks {
+            let start = block_starts[block_starts.len() - 1];
+            block_starts.push(start + block.duration_t(&programs));
+        }
+
+        let mut tape = Self {
+            programs,
+            next_program: 0,
+            format,
+            clock_hz: source_hz,
+            tzx_info: None,
+            source,
+            blocks,
+            source_hz,
+            block_starts,
             playing: false,
-            current_index: 0,
+            block: 0,
+            cursor: Cursor::Done,
+            pulse_start: 0,
+            pulse_length: 0,
             remaining: 0,
             level: false,
+            degrader: None,
+        };
+        tape.seek(0);
+        tape
+    }
+
+    // Every pulse from the start of the tape, made as it's asked for, in T-states of
+    // the clock the tape was made for (see source_hz)
+    pub fn pulses(&self) -> impl Iterator<Item = (bool, u64)> + '_ {
+        Pulses::new(&self.blocks, &self.programs)
+    }
+
+    pub fn source_hz(&self) -> u64 {
+        self.source_hz
+    }
+
+    // Play the tape spoiled in the given ways (None for a clean signal). Only playback
+    // is affected, pulses() and the files written from the tape stay clean. The current
+    // block starts over, so it's spoiled from its first pulse.
+    pub fn set_degradation(&mut self, degradation: Option<Degradation>) {
+        self.degrader = degradation.map(Degrader::new);
+        self.seek(self.block);
+    }
+
+    pub fn degradation(&self) -> Option<&Degradation> {
+        self.degrader.as_ref().map(|degrader| degrader.settings())
+    }
+
+    // Whether take_program would find anything
+    pub fn has_program(&self, name: Option<&str>) -> bool {
+        match name {
+            Some(name) => self
+                .programs
+                .iter()
+                .any(|program| program.name().eq_ignore_ascii_case(name)),
+            None => self.next_program < self.programs.len(),
         }
     }
 
-    fn generate_pulses(data: &Vec<u8>) -> Vec<(bool, u64)> {
-        let mut pulses = Vec::new();
-        pulses.push((false, LEADER_T)); // Long intro silence, low
-
-        // Add 4 sync pulses after leader (this is what the ROM expects)
-        for _ in 0..4 {
-            pulses.push((true, PULSE_HIGH_T));
-            pulses.push((false, PULSE_LOW_T));
-        }
-
-        // Add dummy name: Single inverse character (0xA6) to signal end-of-name
-        let dummy_name: Vec<u8> = vec![0xA6];
-
-        // First, process dummy name bytes
-        for &byte in dummy_name.iter() {
-            for bit_pos in (0..8).rev() {
-                // MSB-first
-                let bit = ((byte >> bit_pos) & 1) != 0;
-                let num_pulses = if bit { 9 } else { 4 };
-                for _ in 0..num_pulses {
-                    pulses.push((true, PULSE_HIGH_T));
-                    pulses.push((false, PULSE_LOW_T));
-                }
-                // Inter-bit silence
-                pulses.push((false, SILENCE_BIT_T));
-            }
+    // Find a program the way LOAD does: by name, or the next one for LOAD ""
+    // Like a real tape the search runs forward, but we wrap round at the end
+    pub fn take_program(&mut self, name: Option<&str>) -> Option<&TapeProgram> {
+        let count = self.programs.len();
+        let index = (0..count)
+            .map(|i| (self.next_program + i) % count)
+            .find(|&i| match name {
+                Some(name) => self.programs[i].name().eq_ignore_ascii_case(name),
+                None => true,
+            })?;
+
+        if name.is_none() && index < self.next_program {
+            // LOAD "" doesn't wrap, once the tape has run out there's nothing left
+            return None;
         }
 
-        for &byte in data.iter() {
-            for bit_pos in (0..8).rev() {
-                // MSB first format
-                let bit = ((byte >> bit_pos) & 1) != 0;
-                let num_pulses = if bit { 9 } else { 4 };
-                for _ in 0..num_pulses {
-                    pulses.push((true, PULSE_HIGH_T));
-                    pulses.push((false, PULSE_LOW_T));
-                }
+        self.next_program = index + 1;
+        Some(&self.programs[index])
+    }
+
+    // One line per program: position, name and size, marking where LOAD "" will start
+    pub fn listing(&self) -> Vec<String> {
+        listing(&self.programs)
+            .into_iter()
+            .enumerate()
+            .map(|(i, line)| {
+                let marker = if i == self.next_program { '>' } else { ' ' };
+                format!("{} {}", marker, line)
+            })
+            .collect()
+    }
 
-                // Add silence between bits
-                pulses.push((false, SILENCE_BIT_T));
-            }
+    // Render the tape as it would sound, for loading on real hardware
+    pub fn save_wav(&self, path: &Path, sample_rate: u32, amplitude: f32) -> Result<(), String> {
+        let bytes = wav::write_wav(self.pulses(), self.source_hz, sample_rate, amplitude);
+        fs::write(path, bytes).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+
+    // Write every program on the tape out as a single .p81 file
+    pub fn save_p81(&self, path: &Path) -> Result<(), String> {
+        if self.format != TapeFormat::Zx81 {
+            return Err("Only ZX81 tapes can be saved as .p81".to_string());
         }
+        fs::write(path, formats::write_p81(&self.programs))
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
 
-        // End pause
-        pulses.push((false, PAUSE_END_T));
-        pulses
+    // Write each program out as NAME.p, e.g. to check what decoded from a recording
+    pub fn extract_p(&self, dir: &Path) -> Result<Vec<PathBuf>, String> {
+        if self.format != TapeFormat::Zx81 {
+            return Err("Only ZX81 tapes can be extracted to .p files".to_string());
+        }
+        self.programs
+            .iter()
+            .map(|program| {
+                let path = dir.join(format!("{}.p", host_file_name(&program.name())));
+                fs::write(&path, formats::write_p(program))
+                    .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+                Ok(path)
+            })
+            .collect()
+    }
+
+    // Pulses are counted for the clock the tape was made for, and scaled as they play
+    pub fn set_clock_hz(&mut self, clock_hz: u64) {
+        self.remaining = self.remaining * clock_hz / self.clock_hz;
+        self.clock_hz = clock_hz;
+    }
+
+    fn to_clock(&self, t: u64) -> u64 {
+        t * self.clock_hz / self.source_hz
     }
 
+    // Play from wherever the tape is, or from the start once it has run out
     pub fn start_playing(&mut self) {
-        if !self.pulses.is_empty() {
+        if self.is_finished() {
+            self.seek(0);
+        }
+        if !self.blocks.is_empty() {
             self.playing = true;
-            self.current_index = 0;
-            self.remaining = self.pulses[0].1;
-            self.level = self.pulses[0].0;
-            println!("Tape playing started!");
         }
     }
 
+    // Stop where we are, playing again carries on from here
+    pub fn stop(&mut self) {
+        self.playing = false;
+    }
+
+    pub fn rewind(&mut self) {
+        self.seek(0);
+    }
+
+    // Skip to the start of the next block, or the end of the tape after the last
+    pub fn fast_forward(&mut self) {
+        self.seek(self.block + 1);
+    }
+
+    // Skip back to the start of the current block, or the one before if we're already there
+    pub fn previous_block(&mut self) {
+        let block = self.current_block();
+        let at_start = !self.is_finished() && self.pulse_start == self.block_starts[block];
+        self.seek(if at_start {
+            block.saturating_sub(1)
+        } else {
+            block
+        });
+    }
+
+    pub fn seek_block(&mut self, block: usize) {
+        self.seek(block);
+    }
+
+    // Straight to the start of a block, whatever came before it
+    fn seek(&mut self, block: usize) {
+        self.enter_block(block.min(self.blocks.len()));
+        self.pulse_length = 0;
+        self.remaining = 0;
+        if !self.next_pulse() {
+            self.playing = false;
+        }
+    }
+
+    // Put the tape at the top of a block. The degrader starts over too, so a block
+    // sounds the same however playback got to it.
+    fn enter_block(&mut self, block: usize) {
+        self.block = block;
+        self.cursor = self.blocks.get(block).map_or(Cursor::Done, Cursor::start);
+        self.pulse_start = self.block_starts[block];
+        if let Some(degrader) = &mut self.degrader {
+            degrader.restart(block);
+        }
+    }
+
+    // Move on to the next pulse, into the next block if this one is done. False once
+    // the tape has run out.
+    fn next_pulse(&mut self) -> bool {
+        self.pulse_start += self.pulse_length;
+        while let Some(block) = self.blocks.get(self.block) {
+            let cursor = &mut self.cursor;
+            let programs = &self.programs;
+            let mut clean = || cursor.next_pulse(block, programs);
+            let pulse = match &mut self.degrader {
+                Some(degrader) => degrader.next_pulse(self.pulse_start, self.source_hz, clean),
+                None => clean(),
+            };
+            if let Some((level, length)) = pulse {
+                self.level = level;
+                self.pulse_length = length;
+                self.remaining = self.to_clock(length);
+                return true;
+            }
+            self.enter_block(self.block + 1);
+        }
+        self.level = false; // Default to low
+        self.pulse_length = 0;
+        self.remaining = 0;
+        false
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.block >= self.blocks.len()
+    }
+
+    // T-states of tape played so far
+    pub fn position_t(&self) -> u64 {
+        (self.to_clock(self.pulse_start) + self.to_clock(self.pulse_length))
+            .saturating_sub(self.remaining)
+    }
+
+    pub fn duration_t(&self) -> u64 {
+        self.to_clock(self.block_starts[self.blocks.len()])
+    }
+
+    pub fn position_secs(&self) -> f64 {
+        self.position_t() as f64 / self.clock_hz as f64
+    }
+
+    pub fn duration_secs(&self) -> f64 {
+        self.duration_t() as f64 / self.clock_hz as f64
+    }
+
+    // Block the tape is in, counting from 0 (a tape with no blocks is all block 0)
+    pub fn current_block(&self) -> usize {
+        self.block.min(self.blocks.len().saturating_sub(1))
+    }
+
+    pub fn block_count(&self) -> usize {
+        self.blocks.len()
+    }
+
     pub fn advance(&mut self, cycles: u64) {
         if !self.playing {
             return;
@@ -115,16 +467,11 @@ impl Tape {
                 break;
             }
             remaining_cycles -= self.remaining;
-            self.current_index += 1;
 
-            if self.current_index >= self.pulses.len() {
+            if !self.next_pulse() {
                 self.playing = false;
-                self.level = false; // Default to low 
-                println!("Tape ended");
                 break;
             }
-            self.level = self.pulses[self.current_index].0;
-            self.remaining = self.pulses[self.current_index].1;
         }
     }
 
@@ -137,22 +484,156 @@ impl Tape {
     }
 }
 
-fn load_p_file(path: &str) -> Result<Vec<u8>, String> {
-    println!("INFO: Loading tape: {}", path);
-    let tape_data = fs::read(path).map_err(|e| format!("Failed to read tape file: {}", e))?;
-
-    let has_header = tape_data.len() > 128 && tape_data[0] == 0x00;
+// Program name for a file: its name without directory or extension
+fn file_stem(path: &Path) -> String {
+    path.file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
 
-    let program_data: Vec<u8> = if has_header {
-        println!("INFO: Detected .p file with header");
-        tape_data[128..].to_vec()
+// Host filename for a ZX81 program name, anything awkward becomes '_'
+pub fn host_file_name(name: &str) -> String {
+    let cleaned: String = name
+        .trim()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if cleaned.is_empty() {
+        "UNNAMED".to_string()
     } else {
-        println!("INFO: Detected .p file without header");
-        tape_data.clone()
-    };
+        cleaned
+    }
+}
+
+fn listing(programs: &[TapeProgram]) -> Vec<String> {
+    programs
+        .iter()
+        .enumerate()
+        .map(|(i, program)| {
+            format!(
+                "{:>3}: \"{}\" ({} bytes)",
+                i + 1,
+                program.name(),
+                program.data.len()
+            )
+        })
+        .collect()
+}
+
+// Every ZX81 tape file in a directory, in name order, as one long tape
+fn load_directory(path: &Path) -> Result<Vec<TapeProgram>, TapeError> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(path)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|p| {
+            p.extension().is_some_and(|ext| {
+                ["p", "p81", "81"]
+                    .iter()
+                    .any(|zx81_ext| ext.eq_ignore_ascii_case(zx81_ext))
+            })
+        })
+        .collect();
+    paths.sort();
+
+    if paths.is_empty() {
+        return Err(TapeError::UnknownFormat(format!(
+            "no .p, .p81 or .81 files in {}",
+            path.display()
+        )));
+    }
+
+    let mut programs = Vec::new();
+    for p in &paths {
+        let extension = p.extension().unwrap_or_default().to_string_lossy();
+        let (file_programs, file_format) = fs::read(p)
+            .map_err(TapeError::from)
+            .and_then(|bytes| parse_programs(&bytes, &file_stem(p), &extension))
+            .map_err(|e| e.in_file(p))?;
+        if file_format == TapeFileFormat::O {
+            return Err(TapeError::UnknownFormat(format!(
+                "{} is a ZX80 tape, not a ZX81 one",
+                p.display()
+            )));
+        }
+        programs.extend(file_programs);
+    }
+    Ok(programs)
+}
+
+// Split a tape file into programs, working out its layout from what's inside it
+fn parse_programs(
+    bytes: &[u8],
+    name: &str,
+    extension: &str,
+) -> Result<(Vec<TapeProgram>, TapeFileFormat), TapeError> {
+    let file_format = formats::detect_format(bytes, extension)
+        .ok_or_else(|| formats::undetected(bytes, extension))?;
+    let programs = formats::parse(bytes, file_format, name)?;
+    Ok((programs, file_format))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::formats::fixtures::p_program;
+    use super::*;
+
+    fn degraded_tape() -> Tape {
+        let programs = ["AA", "BB", "CC"].map(p_program).into();
+        let mut tape = Tape::from_programs(programs, TapeFormat::Zx81);
+        tape.set_degradation(Some(
+            "jitter=0.2,wow=0.05,flutter=0.02,dropouts=20,spikes=200,seed=3"
+                .parse()
+                .unwrap(),
+        ));
+        tape
+    }
+
+    // Every pulse from here to the end, with the block it belongs to
+    fn play_out(tape: &mut Tape) -> Vec<(usize, bool, u64)> {
+        let mut pulses = Vec::new();
+        while !tape.is_finished() {
+            pulses.push((tape.block, tape.level, tape.pulse_length));
+            tape.next_pulse();
+        }
+        pulses
+    }
+
+    #[test]
+    fn degraded_pulses_repeat_after_rewind() {
+        let mut tape = degraded_tape();
+        let first = play_out(&mut tape);
+        assert!(first.iter().any(|&(block, ..)| block == 2));
+
+        tape.rewind();
+        assert_eq!(play_out(&mut tape), first);
+
+        // Stopping part way through a block and rewinding makes no difference either
+        tape.rewind();
+        for _ in 0..first.len() / 2 {
+            tape.next_pulse();
+        }
+        tape.rewind();
+        assert_eq!(play_out(&mut tape), first);
+    }
 
-    println!("INFO: P file size: {}", tape_data.len());
-    println!("INFO: Program data size: {}", program_data.len());
+    #[test]
+    fn degraded_block_sounds_the_same_however_it_is_reached() {
+        let mut tape = degraded_tape();
+        let first = play_out(&mut tape);
+        let from_block_1: Vec<_> = first
+            .iter()
+            .copied()
+            .filter(|&(block, ..)| block >= 1)
+            .collect();
 
-    Ok(program_data)
+        tape.rewind();
+        tape.fast_forward();
+        assert_eq!(play_out(&mut tape), from_block_1);
+
+        tape.seek_block(2);
+        tape.previous_block();
+        tape.previous_block();
+        tape.fast_forward();
+        assert_eq!(play_out(&mut tape), from_block_1);
+    }
 }
@@ -0,0 +1,85 @@
+// MIC output capture
+// A ROM-driven SAVE (or any machine-code saver) makes its tape signal by
+// toggling the MIC/VSYNC line. We keep each change with its T-state, then turn
+// the lot into the same (level, duration) pulses a Tape plays.
+pub struct MicRecorder {
+    started_at: u64,
+    level: bool,             // Tape level: high while VSYNC holds MIC low
+    edges: Vec<(u64, bool)>, // (T-state, new level)
+}
+
+impl MicRecorder {
+    pub fn new(t_state: u64, level: bool) -> Self {
+        Self {
+            started_at: t_state,
+            level,
+            edges: Vec::new(),
+        }
+    }
+
+    pub fn set_level(&mut self, t_state: u64, level: bool) {
+        if level != self.level {
+            self.edges.push((t_state, level));
+            self.level = level;
+        }
+    }
+
+    // Everything recorded up to `now`, as tape pulses
+    pub fn finish(self, now: u64) -> Vec<(bool, u64)> {
+        let mut pulses = Vec::with_capacity(self.edges.len() + 1);
+        let mut level = self.edges.first().map_or(self.level, |&(_, level)| !level);
+        let mut since = self.started_at;
+        for (t_state, new_level) in self.edges.into_iter().chain([(now, !self.level)]) {
+            if t_state > since {
+                pulses.push((level, t_state - since));
+            }
+            level = new_level;
+            since = t_state;
+        }
+        pulses
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::formats::fixtures::{p_image, p_program};
+    use super::super::{Tape, TapeFormat};
+    use super::*;
+    use crate::machine::CLOCK_HZ;
+
+    #[test]
+    fn edges_become_pulses() {
+        let mut recorder = MicRecorder::new(100, false);
+        recorder.set_level(150, true);
+        recorder.set_level(160, true); // No change, no edge
+        recorder.set_level(200, false);
+        recorder.set_level(230, true);
+        assert_eq!(
+            recorder.finish(300),
+            vec![(false, 50), (true, 50), (false, 30), (true, 70)]
+        );
+    }
+
+    #[test]
+    fn recorded_saves_decode_into_blocks_and_programs() {
+        // Toggle MIC the way two ROM SAVEs would
+        let saved = Tape::from_programs(vec![p_program("ONE"), p_program("TWO")], TapeFormat::Zx81);
+        let mut recorder = MicRecorder::new(0, false);
+        let mut t = 0;
+        for (level, length) in saved.pulses() {
+            recorder.set_level(t, level);
+            t += length;
+        }
+
+        let tape = Tape::from_recording(recorder.finish(t), CLOCK_HZ);
+        assert_eq!(tape.block_count(), 2);
+        assert_eq!(tape.duration_t(), saved.duration_t());
+        let names: Vec<String> = tape.programs.iter().map(|program| program.name()).collect();
+        assert_eq!(names, vec!["ONE", "TWO"]);
+        assert!(
+            tape.programs
+                .iter()
+                .all(|program| program.data == p_image(0))
+        );
+    }
+}
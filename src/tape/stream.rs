@@ -0,0 +1,316 @@
+// Tape sound, made as it plays
+// A program's pulses are worked out from its bytes as the cursor passes them,
+// so a tape costs about what its programs do, not 160 pulses per byte. Recorded
+// signals (TZX, WAV, MIC) keep one length per edge.
+use super::{PAUSE_END_T, PULSE_HIGH_T, PULSE_LOW_T, SILENCE_BIT_T, TapeProgram};
+
+const ZERO_PULSES: u8 = 4;
+const ONE_PULSES: u8 = 9;
+const PULSE_T: u64 = PULSE_HIGH_T + PULSE_LOW_T;
+
+// One stretch of tape, from the silence before it up to the next one
+pub enum Block {
+    // A program played the way SAVE records it: leader, name (ZX81 only), data, end pause
+    Program {
+        index: usize, // Into Tape::programs
+        with_name: bool,
+        leader_t: u64,
+    },
+    // Pulses as they were recorded, the level flipping at every one
+    Recorded {
+        first_level: bool,
+        lengths: Vec<u32>,
+    },
+}
+
+impl Block {
+    // Length in T-states of the clock the block was made for
+    pub fn duration_t(&self, programs: &[TapeProgram]) -> u64 {
+        match self {
+            Block::Program {
+                index,
+                with_name,
+                leader_t,
+            } => {
+                let program = &programs[*index];
+                let name: &[u8] = if *with_name { &program.name } else { &[] };
+                let bytes = name.len() + program.data.len();
+                let ones: u64 = name
+                    .iter()
+                    .chain(&program.data)
+                    .map(|byte| byte.count_ones() as u64)
+                    .sum();
+                let zeros = bytes as u64 * 8 - ones;
+                leader_t
+                    + (ones * ONE_PULSES as u64 + zeros * ZERO_PULSES as u64) * PULSE_T
+                    + bytes as u64 * 8 * SILENCE_BIT_T
+                    + PAUSE_END_T
+            }
+            Block::Recorded { lengths, .. } => lengths.iter().map(|&length| length as u64).sum(),
+        }
+    }
+}
+
+// Where playback is within a block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cursor {
+    Leader,
+    // `half` counts the high and low halves of the bit's pulses, then its silence
+    Bit { byte: usize, bit: u8, half: u8 },
+    EndPause,
+    Recorded(usize),
+    Done,
+}
+
+impl Cursor {
+    pub fn start(block: &Block) -> Self {
+        match block {
+            Block::Program { .. } => Cursor::Leader,
+            Block::Recorded { .. } => Cursor::Recorded(0),
+        }
+    }
+
+    // The pulse under the cursor, moving the cursor on past it. None at the end of the block.
+    pub fn next_pulse(&mut self, block: &Block, programs: &[TapeProgram]) -> Option<(bool, u64)> {
+        match block {
+            Block::Program {
+                index,
+                with_name,
+                leader_t,
+            } => {
+                let program = &programs[*index];
+                let name: &[u8] = if *with_name { &program.name } else { &[] };
+                let byte_at = |byte: usize| match byte.checked_sub(name.len()) {
+                    None => name.get(byte).copied(),
+                    Some(offset) => program.data.get(offset).copied(),
+                };
+                self.next_program_pulse(*leader_t, byte_at)
+            }
+            Block::Recorded {
+                first_level,
+                lengths,
+            } => {
+                let Cursor::Recorded(i) = *self else {
+                    return None;
+                };
+                let length = *lengths.get(i)?;
+                *self = Cursor::Recorded(i + 1);
+                Some((*first_level ^ (i % 2 == 1), length as u64))
+            }
+        }
+    }
+
+    fn next_program_pulse(
+        &mut self,
+        leader_t: u64,
+        byte_at: impl Fn(usize) -> Option<u8>,
+    ) -> Option<(bool, u64)> {
+        // Where the bits start, or the end pause for a program with no bytes
+        let first_bit = || match byte_at(0) {
+            Some(_) => Cursor::Bit {
+                byte: 0,
+                bit: 0,
+                half: 0,
+            },
+            None => Cursor::EndPause,
+        };
+
+        match *self {
+            // Long intro silence, low. There's no separate sync tone: LOAD syncs on the
+            // silence and the first pulse after it already belongs to the first bit.
+            Cursor::Leader => {
+                *self = first_bit();
+                Some((false, leader_t))
+            }
+            Cursor::Bit { byte, bit, half } => {
+                // MSB first
+                let value = byte_at(byte)? & (0x80 >> bit) != 0;
+                let pulses = if value { ONE_PULSES } else { ZERO_PULSES };
+                let pulse = match half {
+                    h if h == pulses * 2 => (false, SILENCE_BIT_T),
+                    h if h % 2 == 0 => (true, PULSE_HIGH_T),
+                    _ => (false, PULSE_LOW_T),
+                };
+
+                *self = if half < pulses * 2 {
+                    Cursor::Bit {
+                        byte,
+                        bit,
+                        half: half + 1,
+                    }
+                } else if bit < 7 {
+                    Cursor::Bit {
+                        byte,
+                        bit: bit + 1,
+                        half: 0,
+                    }
+                } else if byte_at(byte + 1).is_some() {
+                    Cursor::Bit {
+                        byte: byte + 1,
+                        bit: 0,
+                        half: 0,
+                    }
+                } else {
+                    Cursor::EndPause
+                };
+                Some(pulse)
+            }
+            Cursor::EndPause => {
+                *self = Cursor::Done;
+                Some((false, PAUSE_END_T))
+            }
+            Cursor::Recorded(_) | Cursor::Done => None,
+        }
+    }
+}
+
+// Every pulse on a tape from the start, in the T-states its blocks were made for
+pub struct Pulses<'a> {
+    blocks: &'a [Block],
+    programs: &'a [TapeProgram],
+    block: usize,
+    cursor: Cursor,
+}
+
+impl<'a> Pulses<'a> {
+    pub fn new(blocks: &'a [Block], programs: &'a [TapeProgram]) -> Self {
+        Self {
+            blocks,
+            programs,
+            block: 0,
+            cursor: blocks.first().map_or(Cursor::Done, Cursor::start),
+        }
+    }
+}
+
+impl Iterator for Pulses<'_> {
+    type Item = (bool, u64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let block = self.blocks.get(self.block)?;
+            if let Some(pulse) = self.cursor.next_pulse(block, self.programs) {
+                return Some(pulse);
+            }
+            self.block += 1;
+            self.cursor = self
+                .blocks
+                .get(self.block)
+                .map_or(Cursor::Done, Cursor::start);
+        }
+    }
+}
+
+// Split a recording into blocks. A block starts with a long silence and runs until
+// the next one. Back to back silences (one block's end pause, the next one's leader)
+// count once, and trailing silence at the end of the tape doesn't start a block.
+pub fn split_recording(pulses: &[(bool, u64)], gap: u64) -> Vec<Block> {
+    let mut starts = Vec::new();
+    let mut gap_start = None;
+    for (i, &(_, length)) in pulses.iter().enumerate() {
+        if length >= gap {
+            gap_start.get_or_insert(i);
+        } else if let Some(start) = gap_start.take() {
+            starts.push(start);
+        }
+    }
+    if starts.first().is_none_or(|&first| first > 0) && !pulses.is_empty() {
+        // Audio before the first silence is a block of its own
+        starts.insert(0, 0);
+    }
+
+    let ends = starts.iter().skip(1).copied().chain([pulses.len()]);
+    starts
+        .iter()
+        .zip(ends)
+        .map(|(&start, end)| recorded_block(&pulses[start..end]))
+        .collect()
+}
+
+// Pack pulses as alternating lengths. Runs at the same level merge, and anything too
+// long for a u32 is split around an empty pulse of the other level.
+fn recorded_block(pulses: &[(bool, u64)]) -> Block {
+    let first_level = pulses.first().is_some_and(|&(level, _)| level);
+    let mut lengths: Vec<u32> = Vec::with_capacity(pulses.len());
+    let mut level = !first_level;
+    for &(pulse_level, mut length) in pulses {
+        if pulse_level == level
+            && let Some(last) = lengths.last_mut()
+        {
+            let room = (u32::MAX - *last) as u64;
+            *last += room.min(length) as u32;
+            length -= room.min(length);
+            if length == 0 {
+                continue;
+            }
+            lengths.push(0);
+        }
+        while length > u32::MAX as u64 {
+            lengths.extend([u32::MAX, 0]);
+            length -= u32::MAX as u64;
+        }
+        lengths.push(length as u32);
+        level = pulse_level;
+    }
+    Block::Recorded {
+        first_level,
+        lengths,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::fixtures::p_program;
+    use super::super::{Tape, TapeFormat};
+    use super::*;
+    use crate::machine::CLOCK_HZ;
+
+    // Program blocks with and without a name, and the same sound recorded
+    fn tapes() -> Vec<Tape> {
+        let programs = || vec![p_program("ONE"), p_program("TWO"), p_program("THREE")];
+        let zx81 = Tape::from_programs(programs(), TapeFormat::Zx81);
+        let recorded = Tape::from_recording(zx81.pulses().collect(), CLOCK_HZ);
+        vec![
+            zx81,
+            Tape::from_programs(programs(), TapeFormat::Zx80),
+            recorded,
+        ]
+    }
+
+    #[test]
+    fn block_duration_is_the_sum_of_its_pulses() {
+        for tape in tapes() {
+            for block in &tape.blocks {
+                let mut cursor = Cursor::start(block);
+                let mut total = 0;
+                while let Some((_, length)) = cursor.next_pulse(block, &tape.programs) {
+                    total += length;
+                }
+                assert_eq!(block.duration_t(&tape.programs), total);
+            }
+        }
+    }
+
+    #[test]
+    fn seeking_to_a_block_lands_where_playing_gets_to() {
+        for i in 0..tapes().len() {
+            let mut played = tapes().swap_remove(i);
+            assert_eq!(played.block_count(), 3);
+            played.start_playing();
+            let mut position = 0;
+            for block in 0..=played.block_count() {
+                played.advance(played.block_starts[block] - position);
+                position = played.block_starts[block];
+
+                let mut seeked = tapes().swap_remove(i);
+                seeked.seek_block(block);
+                assert_eq!(seeked.block, played.block);
+                assert_eq!(seeked.cursor, played.cursor);
+                assert_eq!(seeked.position_t(), played.position_t());
+                assert_eq!(seeked.get_level(), played.get_level());
+                assert_eq!(seeked.remaining, played.remaining);
+            }
+            assert!(played.is_finished());
+        }
+    }
+}
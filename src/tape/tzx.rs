@@ -0,0 +1,466 @@
+use super::formats;
+use super::{TapeError, TapeProgram};
+use crate::machine::CLOCK_HZ;
+
+const SIGNATURE: &[u8] = b"ZXTape!\x1A";
+const HEADER_LEN: usize = 10; // Signature plus major and minor version
+
+// TZX timings are counted in ZX Spectrum T-states
+const TZX_CLOCK_HZ: u64 = 3_500_000;
+
+// A block we skipped over because we can't play it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnsupportedBlock {
+    pub id: u8,
+    pub offset: usize, // Where the block starts in the file
+}
+
+impl UnsupportedBlock {
+    pub fn name(&self) -> &'static str {
+        block_name(self.id)
+    }
+}
+
+// Everything in a TZX file that isn't sound
+#[derive(Debug, Clone, Default)]
+pub struct TzxInfo {
+    pub version: (u8, u8),
+    pub archive_info: Vec<(String, String)>, // (field, value) from block 0x32
+    pub texts: Vec<String>,                  // Text descriptions from block 0x30
+    pub unsupported: Vec<UnsupportedBlock>,
+}
+
+pub struct TzxTape {
+    pub pulses: Vec<(bool, u64)>,   // In ZX81 T-states, like Tape::pulses
+    pub programs: Vec<TapeProgram>, // Decoded from data blocks, for trapped LOADs
+    pub info: TzxInfo,
+}
+
+pub fn is_tzx(bytes: &[u8]) -> bool {
+    bytes.starts_with(SIGNATURE)
+}
+
+pub fn parse(bytes: &[u8]) -> Result<TzxTape, TapeError> {
+    if !is_tzx(bytes) {
+        return Err(TapeError::UnknownFormat(
+            "not a TZX file (missing ZXTape! signature)".to_string(),
+        ));
+    }
+    if bytes.len() < HEADER_LEN {
+        return Err(TapeError::Truncated("TZX header is incomplete".to_string()));
+    }
+
+    let mut builder = PulseBuilder::default();
+    let mut programs = Vec::new();
+    let mut info = TzxInfo {
+        version: (bytes[8], bytes[9]),
+        ..TzxInfo::default()
+    };
+
+    let mut offset = HEADER_LEN;
+    while offset < bytes.len() {
+        let id = bytes[offset];
+        let body = &bytes[offset + 1..];
+        let len = block_len(id, body).ok_or_else(|| {
+            TapeError::Truncated(format!(
+                "TZX block 0x{:02X} ({}) at offset {}",
+                id,
+                block_name(id),
+                offset
+            ))
+        })?;
+        if len > body.len() {
+            return Err(TapeError::Truncated(format!(
+                "TZX block 0x{:02X} ({}) at offset {} runs past the end of the file",
+                id,
+                block_name(id),
+                offset
+            )));
+        }
+        let body = &body[..len];
+
+        match id {
+            0x19 => {
+                let data = parse_generalized_data(body, offset, &mut builder)?;
+                // ZX81 data blocks hold the name followed by the program, like a .p81 entry
+                if let Some(program) = data.and_then(|data| formats::parse_named_program(&data)) {
+                    programs.push(program);
+                }
+            }
+            0x20 => {
+                // A pause of 0 means "stop the tape", which we leave to the user
+                builder.pause(read_word(body, 0) as u64);
+            }
+            0x30 => info.texts.push(read_text(&body[1..])),
+            0x32 => info.archive_info.extend(parse_archive_info(body)),
+            // Group markers and glue blocks carry nothing we need
+            0x21 | 0x22 | 0x5A => {}
+            // Skipped, but recorded so the caller can warn about it
+            _ => {
+                info.unsupported.push(UnsupportedBlock { id, offset });
+            }
+        }
+
+        offset += 1 + len;
+    }
+
+    Ok(TzxTape {
+        pulses: builder.pulses,
+        programs,
+        info,
+    })
+}
+
+// Length of a block's body (everything after the ID byte)
+fn block_len(id: u8, body: &[u8]) -> Option<usize> {
+    let byte = |at: usize| body.get(at).map(|&b| b as usize);
+    let word = |at: usize| Some(byte(at)? | (byte(at + 1)? << 8));
+    let triple = |at: usize| Some(word(at)? | (byte(at + 2)? << 16));
+    let dword = |at: usize| Some(word(at)? | (word(at + 2)? << 16));
+
+    Some(match id {
+        0x10 => 4 + word(2)?,
+        0x11 => 0x12 + triple(0x0F)?,
+        0x12 => 4,
+        0x13 => 1 + byte(0)? * 2,
+        0x14 => 0x0A + triple(0x07)?,
+        0x15 => 0x08 + triple(0x05)?,
+        0x16..=0x19 => 4 + dword(0)?,
+        0x20 | 0x23 | 0x24 => 2,
+        0x21 | 0x30 => 1 + byte(0)?,
+        0x22 | 0x25 | 0x27 => 0,
+        0x26 => 2 + word(0)? * 2,
+        0x28 | 0x32 => 2 + word(0)?,
+        0x2A => 4,
+        0x2B => 5,
+        0x31 => 2 + byte(1)?,
+        0x33 => 1 + byte(0)? * 3,
+        0x34 => 8,
+        0x35 => 0x14 + dword(0x10)?,
+        0x40 => 4 + triple(0x01)?,
+        0x5A => 9,
+        // Blocks from later versions of the format start with their length, so they
+        // can be skipped
+        _ => 4 + dword(0)?,
+    })
+}
+
+fn block_name(id: u8) -> &'static str {
+    match id {
+        0x10 => "standard speed data",
+        0x11 => "turbo speed data",
+        0x12 => "pure tone",
+        0x13 => "pulse sequence",
+        0x14 => "pure data",
+        0x15 => "direct recording",
+        0x16 => "C64 ROM type data",
+        0x17 => "C64 turbo tape data",
+        0x18 => "CSW recording",
+        0x19 => "generalized data",
+        0x20 => "pause",
+        0x21 => "group start",
+        0x22 => "group end",
+        0x23 => "jump to block",
+        0x24 => "loop start",
+        0x25 => "loop end",
+        0x26 => "call sequence",
+        0x27 => "return from sequence",
+        0x28 => "select block",
+        0x2A => "stop the tape if in 48K mode",
+        0x2B => "set signal level",
+        0x30 => "text description",
+        0x31 => "message",
+        0x32 => "archive info",
+        0x33 => "hardware type",
+        0x34 => "emulation info",
+        0x35 => "custom info",
+        0x40 => "snapshot",
+        0x5A => "glue",
+        _ => "unknown",
+    }
+}
+
+// Turns TZX pulse descriptions into (level, duration) pairs in ZX81 T-states
+#[derive(Default)]
+struct PulseBuilder {
+    pulses: Vec<(bool, u64)>,
+    level: bool, // Level of the last pulse, the tape starts low
+}
+
+impl PulseBuilder {
+    // One symbol: the first pulse's edge depends on the flags, later pulses always toggle
+    fn symbol(&mut self, flags: u8, lengths: &[u16]) {
+        for (i, &length) in lengths.iter().enumerate() {
+            if length == 0 {
+                // A zero length ends the symbol early
+                break;
+            }
+            if i == 0 {
+                self.level = match flags & 0x03 {
+                    0 => !self.level,
+                    1 => self.level,
+                    2 => false,
+                    _ => true,
+                };
+            } else {
+                self.level = !self.level;
+            }
+            self.push(self.level, length as u64 * CLOCK_HZ / TZX_CLOCK_HZ);
+        }
+    }
+
+    fn pause(&mut self, ms: u64) {
+        if ms > 0 {
+            self.level = false;
+            self.push(false, ms * CLOCK_HZ / 1000);
+        }
+    }
+
+    // Runs of the same level merge into one pulse
+    fn push(&mut self, level: bool, length: u64) {
+        match self.pulses.last_mut() {
+            Some(last) if last.0 == level => last.1 += length,
+            _ => self.pulses.push((level, length)),
+        }
+    }
+}
+
+// A symbol table entry: edge flags and up to N pulse lengths
+struct Symbol {
+    flags: u8,
+    lengths: Vec<u16>,
+}
+
+// Block 0x19. Returns the raw data bytes when they're plain bits, one per symbol.
+fn parse_generalized_data(
+    body: &[u8],
+    offset: usize,
+    builder: &mut PulseBuilder,
+) -> Result<Option<Vec<u8>>, TapeError> {
+    let truncated =
+        || TapeError::Truncated(format!("TZX block 0x19 at offset {} is cut short", offset));
+    let undefined = |stream: &str| {
+        TapeError::Corrupt(format!(
+            "TZX block 0x19 at offset {}: {} stream uses an undefined symbol",
+            offset, stream
+        ))
+    };
+    let header = body.get(..0x12).ok_or_else(truncated)?;
+
+    let pause = read_word(header, 0x04) as u64;
+    let pilot_count = read_dword(header, 0x06) as usize;
+    let pilot_max_pulses = header[0x0A] as usize;
+    let pilot_symbols = alphabet_size(header[0x0B]);
+    let data_count = read_dword(header, 0x0C) as usize;
+    let data_max_pulses = header[0x10] as usize;
+    let data_symbols = alphabet_size(header[0x11]);
+
+    let mut at = 0x12;
+    let read_symbols = |count: usize, max_pulses: usize, at: &mut usize| {
+        let mut symbols = Vec::with_capacity(count);
+        for _ in 0..count {
+            let entry = body
+                .get(*at..*at + 1 + max_pulses * 2)
+                .ok_or_else(truncated)?;
+            symbols.push(Symbol {
+                flags: entry[0],
+                lengths: (0..max_pulses)
+                    .map(|i| read_word(entry, 1 + i * 2))
+                    .collect(),
+            });
+            *at += entry.len();
+        }
+        Ok::<_, TapeError>(symbols)
+    };
+
+    if pilot_count > 0 {
+        let symbols = read_symbols(pilot_symbols, pilot_max_pulses, &mut at)?;
+        for _ in 0..pilot_count {
+            let entry = body.get(at..at + 3).ok_or_else(truncated)?;
+            let symbol = symbols
+                .get(entry[0] as usize)
+                .ok_or_else(|| undefined("pilot"))?;
+            for _ in 0..read_word(entry, 1) {
+                builder.symbol(symbol.flags, &symbol.lengths);
+            }
+            at += 3;
+        }
+    }
+
+    let mut data = None;
+    if data_count > 0 {
+        let symbols = read_symbols(data_symbols, data_max_pulses, &mut at)?;
+        // A single-symbol alphabet needs no bits at all, the stream is empty
+        let bits_per_symbol = (usize::BITS - (data_symbols - 1).leading_zeros()) as usize;
+        let stream_len = (data_count * bits_per_symbol).div_ceil(8);
+        let stream = body.get(at..at + stream_len).ok_or_else(truncated)?;
+
+        for i in 0..data_count {
+            let mut index = 0;
+            for bit in i * bits_per_symbol..(i + 1) * bits_per_symbol {
+                let set = stream[bit / 8] & (0x80 >> (bit % 8)) != 0;
+                index = (index << 1) | set as usize;
+            }
+            let symbol = symbols.get(index).ok_or_else(|| undefined("data"))?;
+            builder.symbol(symbol.flags, &symbol.lengths);
+        }
+
+        if bits_per_symbol == 1 {
+            data = Some(stream.to_vec());
+        }
+    }
+
+    builder.pause(pause);
+    Ok(data)
+}
+
+// An alphabet size of 0 stands for 256 symbols
+fn alphabet_size(size: u8) -> usize {
+    if size == 0 { 256 } else { size as usize }
+}
+
+fn parse_archive_info(body: &[u8]) -> Vec<(String, String)> {
+    let count = body.get(2).copied().unwrap_or(0);
+    let mut entries = Vec::new();
+    let mut at = 3;
+    for _ in 0..count {
+        let Some(&[id, len]) = body.get(at..at + 2) else {
+            break;
+        };
+        let Some(text) = body.get(at + 2..at + 2 + len as usize) else {
+            break;
+        };
+        entries.push((archive_field_name(id).to_string(), read_text(text)));
+        at += 2 + len as usize;
+    }
+    entries
+}
+
+fn archive_field_name(id: u8) -> &'static str {
+    match id {
+        0x00 => "Title",
+        0x01 => "Publisher",
+        0x02 => "Author",
+        0x03 => "Year",
+        0x04 => "Language",
+        0x05 => "Type",
+        0x06 => "Price",
+        0x07 => "Loader",
+        0x08 => "Origin",
+        _ => "Comment",
+    }
+}
+
+// TZX text is ASCII with CR line breaks
+fn read_text(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).replace('\r', "\n")
+}
+
+fn read_word(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn read_dword(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::formats::fixtures::{p_image, p81_image};
+    use super::*;
+
+    fn tzx(blocks: &[&[u8]]) -> Vec<u8> {
+        let mut bytes = SIGNATURE.to_vec();
+        bytes.extend([1, 20]);
+        for block in blocks {
+            bytes.extend_from_slice(block);
+        }
+        bytes
+    }
+
+    #[test]
+    fn skips_fixed_and_length_prefixed_blocks() {
+        let emulation_info: &[u8] = &[0x34, 1, 2, 3, 4, 5, 6, 7, 8];
+        let snapshot: &[u8] = &[0x40, 0x00, 0x03, 0x00, 0x00, 0xAA, 0xBB, 0xCC];
+        let future: &[u8] = &[0x4B, 0x02, 0x00, 0x00, 0x00, 0x30, 0x30];
+        let text: &[u8] = &[0x30, 0x02, b'H', b'I'];
+        let tape = parse(&tzx(&[emulation_info, snapshot, future, text])).unwrap();
+
+        assert_eq!(tape.info.texts, vec!["HI".to_string()]);
+        let skipped: Vec<(u8, usize)> = tape
+            .info
+            .unsupported
+            .iter()
+            .map(|block| (block.id, block.offset))
+            .collect();
+        assert_eq!(skipped, vec![(0x34, 10), (0x40, 19), (0x4B, 27)]);
+        assert_eq!(tape.info.unsupported[0].name(), "emulation info");
+    }
+
+    #[test]
+    fn block_past_the_end_is_truncated() {
+        let bytes = tzx(&[&[0x34, 1, 2, 3]]);
+        assert!(matches!(parse(&bytes), Err(TapeError::Truncated(_))));
+    }
+
+    // Block 0x19: a one-pulse pilot symbol played `pilot` times, then `data_count`
+    // symbols read from `stream`, out of [short, short] and (if `symbols` is 2)
+    // [long, long]
+    fn generalized_block(pilot: u16, symbols: u8, data_count: u32, stream: &[u8]) -> Vec<u8> {
+        let mut body = vec![0; 0x12];
+        body[0x04..0x06].copy_from_slice(&1u16.to_le_bytes()); // 1 ms pause
+        body[0x06..0x0A].copy_from_slice(&1u32.to_le_bytes());
+        body[0x0A] = 1;
+        body[0x0B] = 1;
+        body[0x0C..0x10].copy_from_slice(&data_count.to_le_bytes());
+        body[0x10] = 2;
+        body[0x11] = symbols;
+        body.extend([0x00, 0x78, 0x05]); // Pilot symbol: one 1400 T pulse
+        body.push(0x00);
+        body.extend(pilot.to_le_bytes());
+        body.extend([0x00, 0x5E, 0x01, 0x5E, 0x01]); // 0: two 350 T pulses
+        if symbols > 1 {
+            body.extend([0x00, 0xBC, 0x02, 0xBC, 0x02]); // 1: two 700 T pulses
+        }
+        body.extend_from_slice(stream);
+
+        let len = body.len() as u32 - 4;
+        body[0x00..0x04].copy_from_slice(&len.to_le_bytes());
+        let mut block = vec![0x19];
+        block.extend(body);
+        block
+    }
+
+    #[test]
+    fn generalized_data_block_plays_and_decodes() {
+        let data = p81_image("GDB");
+        let block = generalized_block(3, 2, data.len() as u32 * 8, &data);
+        let tape = parse(&tzx(&[&block])).unwrap();
+
+        // TZX lengths are in 3.5 MHz T-states, ours at 3.25 MHz
+        let mut expected = vec![(true, 1300), (false, 1300), (true, 1300)];
+        for byte in &data {
+            for bit in (0..8).rev() {
+                let length = if byte & (1 << bit) != 0 { 650 } else { 325 };
+                expected.extend([(false, length), (true, length)]);
+            }
+        }
+        expected.push((false, 3250));
+        assert_eq!(tape.pulses, expected);
+
+        assert_eq!(tape.programs.len(), 1);
+        assert_eq!(tape.programs[0].name(), "GDB");
+        assert_eq!(tape.programs[0].data, p_image(0));
+    }
+
+    #[test]
+    fn single_symbol_alphabet_takes_no_stream_bytes() {
+        let block = generalized_block(0, 1, 2, &[]);
+        let tape = parse(&tzx(&[&block])).unwrap();
+
+        // The last pulse runs on into the pause
+        let expected = vec![(true, 325), (false, 325), (true, 325), (false, 325 + 3250)];
+        assert_eq!(tape.pulses, expected);
+        assert!(tape.programs.is_empty());
+    }
+}
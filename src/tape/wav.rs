@@ -0,0 +1,408 @@
+use super::{TapeError, TapeProgram, formats};
+
+// Span of the running average taken off as DC offset, a couple of pulse periods
+const DC_WINDOW_SEC: f32 = 0.002;
+// Schmitt trigger thresholds, as a fraction of the loudest sample
+const HYSTERESIS: f32 = 0.15;
+
+// Decoder timings in T-states. A bit is a burst of 975 T pulses followed by
+// 4225 T of silence, a program is preceded by seconds of it.
+const BIT_GAP_T: u64 = 2000;
+const PROGRAM_GAP_T: u64 = 250_000;
+const ONE_BIT_PULSES: u32 = 7; // Bursts of 4 are a 0, 9 a 1
+const MIN_BIT_PULSES: u32 = 3; // Anything shorter is a click, or a VSYNC between saves
+
+// A PCM recording, mixed down to mono
+pub struct WavAudio {
+    pub sample_rate: u32,
+    pub samples: Vec<f32>, // -1.0 to 1.0
+}
+
+pub fn is_wav(bytes: &[u8]) -> bool {
+    bytes.len() >= 12 && &bytes[0..4] == b"RIFF" && &bytes[8..12] == b"WAVE"
+}
+
+// 8-bit (unsigned) or 16-bit (signed) PCM, any number of channels
+pub fn read_wav(bytes: &[u8]) -> Result<WavAudio, TapeError> {
+    if !is_wav(bytes) {
+        return Err(TapeError::UnknownFormat(
+            "not a WAV file (missing RIFF/WAVE header)".to_string(),
+        ));
+    }
+
+    let mut format = None;
+    let mut data = None;
+    let mut offset = 12;
+    while offset + 8 <= bytes.len() {
+        let id = &bytes[offset..offset + 4];
+        let len = u32::from_le_bytes([
+            bytes[offset + 4],
+            bytes[offset + 5],
+            bytes[offset + 6],
+            bytes[offset + 7],
+        ]) as usize;
+        let body = &bytes[offset + 8..(offset + 8 + len).min(bytes.len())];
+        match id {
+            b"fmt " => format = Some(body),
+            b"data" => data = Some(body),
+            _ => {}
+        }
+        // Chunks are padded to an even length
+        offset += 8 + len + (len & 1);
+    }
+
+    let truncated = |what: &str| TapeError::Truncated(format!("WAV file {}", what));
+    let format = format.ok_or_else(|| truncated("has no fmt chunk"))?;
+    let data = data.ok_or_else(|| truncated("has no data chunk"))?;
+    if format.len() < 16 {
+        return Err(truncated("fmt chunk is too short"));
+    }
+
+    let audio_format = u16::from_le_bytes([format[0], format[1]]);
+    let channels = u16::from_le_bytes([format[2], format[3]]) as usize;
+    let sample_rate = u32::from_le_bytes([format[4], format[5], format[6], format[7]]);
+    let bits = u16::from_le_bytes([format[14], format[15]]);
+
+    // 0xFFFE is WAVE_FORMAT_EXTENSIBLE, which still holds plain PCM for our purposes
+    if audio_format != 1 && audio_format != 0xFFFE {
+        return Err(TapeError::UnknownFormat(format!(
+            "WAV encoding {} (only PCM is supported)",
+            audio_format
+        )));
+    }
+    if channels == 0 || sample_rate == 0 {
+        return Err(TapeError::Corrupt(
+            "WAV file has no channels or a zero sample rate".to_string(),
+        ));
+    }
+
+    let sample_bytes = match bits {
+        8 => 1,
+        16 => 2,
+        _ => {
+            return Err(TapeError::UnknownFormat(format!(
+                "{}-bit WAV samples (only 8 and 16 are supported)",
+                bits
+            )));
+        }
+    };
+
+    let samples = data
+        .chunks_exact(sample_bytes * channels)
+        .map(|frame| {
+            let sum: f32 = frame
+                .chunks_exact(sample_bytes)
+                .map(|sample| match sample_bytes {
+                    1 => (sample[0] as f32 - 128.0) / 128.0,
+                    _ => i16::from_le_bytes([sample[0], sample[1]]) as f32 / 32768.0,
+                })
+                .sum();
+            sum / channels as f32
+        })
+        .collect();
+
+    Ok(WavAudio {
+        sample_rate,
+        samples,
+    })
+}
+
+// Clean up a recording into the (level, duration) pulses the EAR bit reads
+pub fn condition(audio: &WavAudio, clock_hz: u64) -> Vec<(bool, u64)> {
+    // Schmitt trigger: the level only flips once the signal clears the far threshold
+    let peak = dc_removed(audio).fold(0.0f32, |peak, s| peak.max(s.abs()));
+    let threshold = peak * HYSTERESIS;
+
+    let to_t = |samples: usize| samples as u64 * clock_hz / audio.sample_rate as u64;
+    let mut pulses = Vec::new();
+    let mut level = false;
+    let mut run_start = 0; // Sample where the current level began
+
+    for (i, sample) in dc_removed(audio).enumerate() {
+        let new_level = if sample > threshold {
+            true
+        } else if sample < -threshold {
+            false
+        } else {
+            level
+        };
+        if new_level != level {
+            if i > run_start {
+                pulses.push((level, to_t(i) - to_t(run_start)));
+            }
+            level = new_level;
+            run_start = i;
+        }
+    }
+    if audio.samples.len() > run_start {
+        pulses.push((level, to_t(audio.samples.len()) - to_t(run_start)));
+    }
+
+    pulses
+}
+
+// The samples less a centred running average, so a drifting baseline doesn't upset
+// the trigger. Worked out as they're read rather than kept, recordings can be long.
+fn dc_removed(audio: &WavAudio) -> impl Iterator<Item = f32> + '_ {
+    let samples = &audio.samples;
+    let half_window = ((audio.sample_rate as f32 * DC_WINDOW_SEC) as usize / 2).max(1);
+    let mut sum: f64 = samples
+        .iter()
+        .take(half_window + 1)
+        .map(|&s| s as f64)
+        .sum();
+
+    samples.iter().enumerate().map(move |(i, &sample)| {
+        let start = i.saturating_sub(half_window);
+        let end = (i + half_window + 1).min(samples.len());
+        let average = sum / (end - start) as f64;
+
+        // Slide the window on for the next sample
+        if let Some(&next) = samples.get(end) {
+            sum += next as f64;
+        }
+        if i >= half_window {
+            sum -= samples[start] as f64;
+        }
+        sample - average as f32
+    })
+}
+
+// Recover ZX81 programs from tape pulses (recorded, captured or generated)
+// by counting the pulses in each bit
+pub fn decode_programs(pulses: &[(bool, u64)], clock_hz: u64) -> Vec<TapeProgram> {
+    // Gaps are measured against the 3.25MHz timings above
+    let scale = |t: u64| t * clock_hz / crate::machine::CLOCK_HZ;
+    let bit_gap = scale(BIT_GAP_T);
+    let program_gap = scale(PROGRAM_GAP_T);
+
+    let mut programs = Vec::new();
+    let mut bytes = Vec::new();
+    let mut byte = 0u8;
+    let mut bits = 0;
+    let mut count = 0u32;
+    let mut idle_level = false; // Level of the silences, whichever way up the recording is
+
+    let mut finish_program = |bytes: &mut Vec<u8>| {
+        // Bytes that don't form a program are noise, or a header we can't use
+        if let Some(program) = formats::parse_named_program(bytes) {
+            programs.push(program);
+        }
+        bytes.clear();
+    };
+
+    for &(level, duration) in pulses {
+        if duration < bit_gap {
+            if level != idle_level {
+                count += 1;
+            }
+            continue;
+        }
+
+        idle_level = level;
+        if count >= MIN_BIT_PULSES {
+            byte = (byte << 1) | (count >= ONE_BIT_PULSES) as u8;
+            bits += 1;
+            if bits == 8 {
+                bytes.push(byte);
+                bits = 0;
+            }
+        }
+        count = 0;
+        if duration >= program_gap {
+            finish_program(&mut bytes);
+            bits = 0;
+        }
+    }
+    finish_program(&mut bytes);
+
+    programs
+}
+
+// Render tape pulses as 16-bit mono PCM. High pulses swing positive and the low
+// half of each pulse negative, longer lows settle back to the centre line as
+// silence so the recording has no DC offset.
+pub fn write_wav(
+    pulses: impl IntoIterator<Item = (bool, u64)>,
+    clock_hz: u64,
+    sample_rate: u32,
+    amplitude: f32,
+) -> Vec<u8> {
+    let peak = (amplitude.clamp(0.0, 1.0) * i16::MAX as f32) as i16;
+    let pulse_low_t = super::PULSE_LOW_T * clock_hz / crate::machine::CLOCK_HZ;
+    let to_sample = |t: u64| (t as u128 * sample_rate as u128 / clock_hz as u128) as usize;
+
+    let mut samples: Vec<i16> = Vec::new();
+    let mut t = 0u64;
+    let mut render = |level: i16, length: u64, t: &mut u64| {
+        *t += length;
+        samples.resize(to_sample(*t), level);
+    };
+    for (level, length) in pulses {
+        if level {
+            render(peak, length, &mut t);
+        } else {
+            let swing = length.min(pulse_low_t);
+            render(-peak, swing, &mut t);
+            render(0, length - swing, &mut t);
+        }
+    }
+
+    pcm_wav(&samples, sample_rate)
+}
+
+// Wrap signed 16-bit mono samples in a WAV header
+fn pcm_wav(samples: &[i16], sample_rate: u32) -> Vec<u8> {
+    let data: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+    let mut bytes = Vec::with_capacity(44 + data.len());
+    bytes.extend_from_slice(b"RIFF");
+    bytes.extend_from_slice(&(36 + data.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(b"WAVE");
+    bytes.extend_from_slice(b"fmt ");
+    bytes.extend_from_slice(&16u32.to_le_bytes());
+    bytes.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    bytes.extend_from_slice(&1u16.to_le_bytes()); // Mono
+    bytes.extend_from_slice(&sample_rate.to_le_bytes());
+    bytes.extend_from_slice(&(sample_rate * 2).to_le_bytes()); // Bytes per second
+    bytes.extend_from_slice(&2u16.to_le_bytes()); // Bytes per sample
+    bytes.extend_from_slice(&16u16.to_le_bytes()); // Bits per sample
+    bytes.extend_from_slice(b"data");
+    bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&data);
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::formats::fixtures::{p_image, p_program, p81_image};
+    use super::super::{Tape, TapeFormat};
+    use super::*;
+    use crate::machine::CLOCK_HZ;
+
+    // A PCM WAV file around `data`, which is already interleaved
+    fn wav(channels: u16, bits: u16, sample_rate: u32, data: &[u8]) -> Vec<u8> {
+        let mut bytes = b"RIFF".to_vec();
+        bytes.extend((36 + data.len() as u32).to_le_bytes());
+        bytes.extend(b"WAVEfmt ");
+        bytes.extend(16u32.to_le_bytes());
+        bytes.extend(1u16.to_le_bytes());
+        bytes.extend(channels.to_le_bytes());
+        bytes.extend(sample_rate.to_le_bytes());
+        bytes.extend((sample_rate * (channels * bits / 8) as u32).to_le_bytes());
+        bytes.extend((channels * bits / 8).to_le_bytes());
+        bytes.extend(bits.to_le_bytes());
+        bytes.extend(b"data");
+        bytes.extend((data.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    fn pcm16(samples: &[i16]) -> Vec<u8> {
+        samples.iter().flat_map(|s| s.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn reads_8_bit_unsigned_samples() {
+        let audio = read_wav(&wav(1, 8, 22050, &[0x80, 0xC0, 0x00])).unwrap();
+        assert_eq!(audio.sample_rate, 22050);
+        assert_eq!(audio.samples, vec![0.0, 0.5, -1.0]);
+    }
+
+    #[test]
+    fn reads_16_bit_signed_samples() {
+        let audio = read_wav(&wav(1, 16, 44100, &pcm16(&[0, 16384, -32768]))).unwrap();
+        assert_eq!(audio.sample_rate, 44100);
+        assert_eq!(audio.samples, vec![0.0, 0.5, -1.0]);
+    }
+
+    #[test]
+    fn mixes_stereo_down_to_mono() {
+        let data = pcm16(&[16384, -16384, 16384, 16384, -32768, 0]);
+        let audio = read_wav(&wav(2, 16, 44100, &data)).unwrap();
+        assert_eq!(audio.samples, vec![0.0, 0.5, -0.5]);
+    }
+
+    #[test]
+    fn bad_headers_are_errors() {
+        let good = wav(1, 16, 44100, &pcm16(&[0; 4]));
+        assert!(read_wav(&good).is_ok());
+
+        assert!(matches!(
+            read_wav(b"RIFX\0\0\0\0WAVE"),
+            Err(TapeError::UnknownFormat(_))
+        ));
+        // Cut off before the data chunk
+        assert!(matches!(
+            read_wav(&good[..36]),
+            Err(TapeError::Truncated(_))
+        ));
+        // fmt chunk too short to hold the sample format
+        let mut short_fmt = good.clone();
+        short_fmt[16..20].copy_from_slice(&12u32.to_le_bytes());
+        assert!(matches!(read_wav(&short_fmt), Err(TapeError::Truncated(_))));
+
+        let mut adpcm = good.clone();
+        adpcm[20..22].copy_from_slice(&2u16.to_le_bytes());
+        assert!(matches!(read_wav(&adpcm), Err(TapeError::UnknownFormat(_))));
+        assert!(matches!(
+            read_wav(&wav(1, 24, 44100, &[0; 6])),
+            Err(TapeError::UnknownFormat(_))
+        ));
+        assert!(matches!(
+            read_wav(&wav(0, 16, 44100, &[])),
+            Err(TapeError::Corrupt(_))
+        ));
+    }
+
+    #[test]
+    fn condition_squares_up_a_signal_with_an_offset() {
+        // A quiet square wave riding on a DC offset, 10 samples each way
+        let samples = (0..200)
+            .map(|i| if i / 10 % 2 == 1 { 0.6 } else { 0.2 })
+            .collect();
+        let audio = WavAudio {
+            sample_rate: 8000,
+            samples,
+        };
+        // One T-state per sample
+        let pulses = condition(&audio, 8000);
+
+        let expected: Vec<(bool, u64)> = (0..20).map(|i| (i % 2 == 1, 10)).collect();
+        assert_eq!(pulses, expected);
+    }
+
+    #[test]
+    fn decodes_a_program_from_pulses() {
+        let mut pulses = vec![(false, CLOCK_HZ)];
+        for byte in p81_image("PULSE") {
+            for bit in (0..8).rev() {
+                let count = if byte & (1 << bit) != 0 { 9 } else { 4 };
+                for _ in 0..count {
+                    pulses.extend([(true, 488), (false, 487)]);
+                }
+                pulses.push((false, 4225));
+            }
+        }
+        pulses.push((false, CLOCK_HZ));
+
+        let programs = decode_programs(&pulses, CLOCK_HZ);
+        assert_eq!(programs.len(), 1);
+        assert_eq!(programs[0].name(), "PULSE");
+        assert_eq!(programs[0].data, p_image(0));
+    }
+
+    #[test]
+    fn written_wav_decodes_back_to_the_same_program() {
+        let tape = Tape::from_programs(vec![p_program("ROUND")], TapeFormat::Zx81);
+        let bytes = write_wav(tape.pulses(), tape.clock_hz, 44100, 0.8);
+
+        let audio = read_wav(&bytes).unwrap();
+        assert_eq!(audio.sample_rate, 44100);
+        let pulses = condition(&audio, CLOCK_HZ);
+        let programs = decode_programs(&pulses, CLOCK_HZ);
+
+        assert_eq!(programs.len(), 1);
+        assert_eq!(programs[0].name, tape.programs[0].name);
+        assert_eq!(programs[0].data, tape.programs[0].data);
+    }
+}
@@ -0,0 +1,107 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::cpu::Cpu;
+use crate::memory::{Memory, RomInfo, TapeTraps};
+use crate::tape::{TapeDeck, TapeFormat};
+
+mod tape;
+pub use tape::{load_hook, save_hook, zx80_load_hook, zx80_save_hook};
+
+// Everything a trap handler is allowed to touch
+pub struct TrapContext<'a> {
+    pub cpu: &'a mut Cpu,
+    pub memory: &'a mut Memory,
+    pub deck: &'a mut TapeDeck,
+    pub rom: &'static RomInfo,
+    pub save_dir: &'a Path,
+}
+
+// A trap handler runs before the instruction at its address is executed.
+// Returning Some(cycles) means the trap has dealt with it (and has set PC to
+// wherever execution should continue), None lets the CPU execute as normal.
+pub type TrapHandler = fn(&mut TrapContext) -> Option<u8>;
+
+// How LOAD and SAVE are serviced
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TapeMode {
+    Trapped, // Intercept the ROM routines and load/save instantly
+    Rom,     // Let the genuine ROM routines drive the tape signal
+    Turbo,   // Genuine routines, unthrottled and undrawn while they're sampling the tape
+}
+
+impl TapeMode {
+    // Whether the LOAD/SAVE traps are installed in this mode
+    pub fn uses_traps(&self) -> bool {
+        *self == TapeMode::Trapped
+    }
+}
+
+pub struct Traps {
+    handlers: HashMap<u16, TrapHandler>,
+    tape_mode: TapeMode, // Trapped, or the speed the ROM routines run at
+}
+
+impl Default for Traps {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Traps {
+    pub fn new() -> Self {
+        Self {
+            handlers: HashMap::new(),
+            tape_mode: TapeMode::Rom,
+        }
+    }
+
+    pub fn register(&mut self, addr: u16, handler: TrapHandler) {
+        self.handlers.insert(addr, handler);
+    }
+
+    pub fn unregister(&mut self, addr: u16) {
+        self.handlers.remove(&addr);
+    }
+
+    pub fn get(&self, addr: u16) -> Option<TrapHandler> {
+        self.handlers.get(&addr).copied()
+    }
+
+    pub fn tape_mode(&self) -> TapeMode {
+        self.tape_mode
+    }
+
+    // Install or remove the LOAD/SAVE traps for the given mode. A ROM we can't
+    // trap only gets the choice of ROM speeds.
+    pub fn set_tape_mode(
+        &mut self,
+        mode: TapeMode,
+        tape_traps: Option<TapeTraps>,
+    ) -> Result<(), String> {
+        let Some(tape_traps) = tape_traps else {
+            if mode.uses_traps() {
+                return Err("No tape traps known".to_string());
+            }
+            self.tape_mode = mode;
+            return Ok(());
+        };
+
+        match mode {
+            TapeMode::Trapped => {
+                let (load, save): (TrapHandler, TrapHandler) = match tape_traps.format {
+                    TapeFormat::Zx81 => (load_hook, save_hook),
+                    TapeFormat::Zx80 => (zx80_load_hook, zx80_save_hook),
+                };
+                self.register(tape_traps.load, load);
+                self.register(tape_traps.save, save);
+            }
+            TapeMode::Rom | TapeMode::Turbo => {
+                self.unregister(tape_traps.load);
+                self.unregister(tape_traps.save);
+            }
+        }
+        self.tape_mode = mode;
+        Ok(())
+    }
+}
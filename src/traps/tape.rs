@@ -0,0 +1,173 @@
+use std::fs;
+
+use super::TrapContext;
+use crate::charset;
+use crate::memory::Memory;
+use crate::tape::{TapeEvent, TapeFormat, host_file_name};
+
+const E_LINE: u16 = 0x4014; // System variable marking the end of the saved area
+const ZX80_E_LINE: u16 = 0x400A;
+const MAX_NAME_LEN: u16 = 127;
+
+pub fn load_hook(ctx: &mut TrapContext) -> Option<u8> {
+    // DE contains the address of the filename (or >= 0x8000 for LOAD "")
+    let name_addr = ctx.cpu.de();
+    let memory = &mut *ctx.memory;
+
+    let name = if name_addr & 0x8000 != 0 {
+        None
+    } else {
+        Some(read_name(memory, name_addr))
+    };
+
+    ctx.deck
+        .report(TapeEvent::LoadRequested { name: name.clone() });
+
+    let no_tape = ctx.deck.is_empty();
+    let event = match ctx.deck.take_program(name.as_deref()) {
+        // The ROM copies everything from VERSN up to E_LINE, system variables included
+        Some(program) => match memory.load_program(&program.data) {
+            Ok(sysvars) => TapeEvent::Loaded {
+                name: program.name(),
+                bytes: program.data.len(),
+                sysvars: Some(sysvars),
+            },
+            Err(e) => TapeEvent::LoadFailed {
+                name: program.name(),
+                error: e.to_string(),
+            },
+        },
+        None if no_tape => TapeEvent::NoTape,
+        None => TapeEvent::NotFound,
+    };
+    // Carry clear for success, set for an error
+    ctx.cpu
+        .set_flag_c(!matches!(event, TapeEvent::Loaded { .. }));
+    ctx.deck.report(event);
+
+    resume(ctx);
+    Some(4)
+}
+
+pub fn save_hook(ctx: &mut TrapContext) -> Option<u8> {
+    // HL points at the program name
+    let name_addr = ctx.cpu.hl();
+    let name = read_name(ctx.memory, name_addr);
+
+    // A real ZX81 saves everything from VERSN up to (but not including) E_LINE
+    let Some(data) = ctx.memory.save_program() else {
+        ctx.deck.report(TapeEvent::SaveFailed {
+            error: format!(
+                "E_LINE (0x{:04X}) is below the program area, nothing to save",
+                ctx.memory.read_word(E_LINE)
+            ),
+        });
+        resume(ctx);
+        return Some(4);
+    };
+
+    let path = ctx.save_dir.join(format!("{}.p", host_file_name(&name)));
+    let event = match fs::write(&path, &data) {
+        Ok(()) => TapeEvent::Saved {
+            path,
+            bytes: data.len(),
+        },
+        Err(e) => TapeEvent::SaveFailed {
+            error: format!("Failed to save {}: {}", path.display(), e),
+        },
+    };
+    ctx.deck.report(event);
+
+    ctx.cpu.set_flag_c(false);
+
+    resume(ctx);
+    Some(4)
+}
+
+// The ZX80's LOAD has no name to look for, it takes the next program on the tape
+pub fn zx80_load_hook(ctx: &mut TrapContext) -> Option<u8> {
+    ctx.deck.report(TapeEvent::LoadRequested { name: None });
+
+    let no_tape = ctx.deck.is_empty();
+    let program = ctx
+        .deck
+        .take_program(None)
+        .map(|program| (program.name(), program.data.clone()));
+    let format = ctx.deck.tape().map(|tape| tape.format);
+    let event = match program {
+        // A ZX81 program would land 9 bytes out, so it's refused rather than loaded
+        Some((name, _)) if format != Some(TapeFormat::Zx80) => TapeEvent::LoadFailed {
+            name,
+            error: "not a ZX80 program".to_string(),
+        },
+        Some((name, data)) => match ctx.memory.load_zx80_program(&data) {
+            Ok(bytes) => TapeEvent::Loaded {
+                name,
+                bytes,
+                sysvars: None,
+            },
+            Err(e) => TapeEvent::LoadFailed {
+                name,
+                error: e.to_string(),
+            },
+        },
+        None if no_tape => TapeEvent::NoTape,
+        None => TapeEvent::NotFound,
+    };
+    ctx.deck.report(event);
+
+    resume(ctx);
+    Some(4)
+}
+
+// The ZX80's SAVE has no name either, so each one goes to the next free zx80-N.o
+pub fn zx80_save_hook(ctx: &mut TrapContext) -> Option<u8> {
+    let Some(data) = ctx.memory.save_zx80_program() else {
+        ctx.deck.report(TapeEvent::SaveFailed {
+            error: format!(
+                "E_LINE (0x{:04X}) is below the program area, nothing to save",
+                ctx.memory.read_word(ZX80_E_LINE)
+            ),
+        });
+        resume(ctx);
+        return Some(4);
+    };
+
+    let path = (1..)
+        .map(|n| ctx.save_dir.join(format!("zx80-{}.o", n)))
+        .find(|path| !path.exists())
+        .expect("ran out of file names");
+    let event = match fs::write(&path, &data) {
+        Ok(()) => TapeEvent::Saved {
+            path,
+            bytes: data.len(),
+        },
+        Err(e) => TapeEvent::SaveFailed {
+            error: format!("Failed to save {}: {}", path.display(), e),
+        },
+    };
+    ctx.deck.report(event);
+
+    resume(ctx);
+    Some(4)
+}
+
+// Program names in memory end with their last character in inverse video
+fn read_name(memory: &Memory, addr: u16) -> String {
+    let mut name_bytes = Vec::new();
+    for i in 0..MAX_NAME_LEN {
+        let byte = memory.read(addr.wrapping_add(i));
+        name_bytes.push(byte);
+        if byte & charset::INVERSE != 0 {
+            break;
+        }
+    }
+    charset::decode_name(&name_bytes)
+}
+
+// Carry on from where the ROM's LOAD/SAVE would have finished
+fn resume(ctx: &mut TrapContext) {
+    if let Some(tape_traps) = ctx.rom.tape_traps {
+        ctx.cpu.pc = tape_traps.resume;
+    }
+}
@@ -1,10 +1,12 @@
 use crate::cpu::Cpu;
+use crate::machine::FrontendOptions;
 use crate::memory::Memory;
-use minifb::{Window, WindowOptions};
+use minifb::{InputCallback, Window, WindowOptions};
+use std::cell::RefCell;
+use std::rc::Rc;
 
 const ZX81_SCREEN_WIDTH: usize = 256; // Screen width
 const ZX81_SCREEN_HEIGHT: usize = 192; // Screen height
-const ZX81_SCREEN_SF: usize = 3; // Scale factor (to fit modern displays)
 const ZX81_DEBUG_PANEL_WIDTH: usize = 320;
 const ZX81_DEBUG_PANEL_HEIGHT: usize = 150;
 
@@ -13,22 +15,38 @@ const FONT_WIDTH: usize = 5;
 const FONT_HEIGHT: usize = 7;
 const FONT_SCALE: usize = 2;
 
+// Collects the characters the host keyboard types, which the window hands over
+// one at a time as they happen
+struct TypedChars(Rc<RefCell<Vec<char>>>);
+
+impl InputCallback for TypedChars {
+    fn add_char(&mut self, uni_char: u32) {
+        if let Some(c) = char::from_u32(uni_char) {
+            self.0.borrow_mut().push(c);
+        }
+    }
+}
+
 // ZX81 Video system
 // Character-based display: 32×24 text
 // Display generated by CPU in SLOW mode
 pub struct Video {
-    window: minifb::Window,
+    window: Option<minifb::Window>, // None when running headless
     buffer: Vec<u32>,
     width: usize,
     height: usize,
     rev_video: bool,
     debug_enabled: bool,
+    scale: usize,
+    charset_addr: usize,           // Where the character bitmaps live in ROM
+    typed: Rc<RefCell<Vec<char>>>, // Characters typed since last taken
 }
 
 impl Video {
-    pub fn new(debug_enabled: bool, rev_video: bool) -> Result<Self, minifb::Error> {
-        let screen_width = ZX81_SCREEN_WIDTH * ZX81_SCREEN_SF;
-        let screen_height = ZX81_SCREEN_HEIGHT * ZX81_SCREEN_SF;
+    pub fn new(frontend: &FrontendOptions, charset_addr: u16) -> Result<Self, minifb::Error> {
+        let debug_enabled = frontend.debug_panel;
+        let screen_width = ZX81_SCREEN_WIDTH * frontend.scale;
+        let screen_height = ZX81_SCREEN_HEIGHT * frontend.scale;
 
         let total_width = if debug_enabled {
             screen_width + ZX81_DEBUG_PANEL_WIDTH
@@ -42,12 +60,19 @@ impl Video {
             screen_height
         };
 
-        let window = Window::new(
-            "ZX81 Emulator",
-            total_width,
-            total_height,
-            WindowOptions::default(),
-        )?;
+        let typed = Rc::new(RefCell::new(Vec::new()));
+        let window = if frontend.headless {
+            None
+        } else {
+            let mut window = Window::new(
+                "ZX81 Emulator",
+                total_width,
+                total_height,
+                WindowOptions::default(),
+            )?;
+            window.set_input_callback(Box::new(TypedChars(Rc::clone(&typed))));
+            Some(window)
+        };
         let buffer = vec![0; total_width * total_height];
 
         println!("Window size: {} x {}", total_width, total_height);
@@ -64,15 +89,18 @@ impl Video {
             buffer,
             width: total_width,
             height: total_height,
-            rev_video,
+            rev_video: frontend.rev_video,
             debug_enabled,
+            scale: frontend.scale,
+            charset_addr: charset_addr as usize,
+            typed,
         })
     }
 
     pub fn render(&mut self, memory: &Memory, rom: &[u8], cpu: &Cpu) {
         let d_file_ptr = memory.read_word(0x400C);
 
-        if d_file_ptr < 0x4000 || d_file_ptr > 0x8000 {
+        if !(0x4000..=0x8000).contains(&d_file_ptr) {
             return;
         }
 
@@ -109,6 +137,20 @@ impl Video {
         }
     }
 
+    // No sync from the machine, so nothing but a blank raster
+    pub fn render_no_signal(&mut self, memory: &Memory, cpu: &Cpu) {
+        let bg_colour = if self.rev_video {
+            0xFFFFFFFF
+        } else {
+            0xFF000000
+        };
+        self.buffer.fill(bg_colour);
+
+        if self.debug_enabled {
+            self.render_debug_panel(cpu, memory);
+        }
+    }
+
     fn render_character(&mut self, char_code: u8, col: usize, line: usize, rom: &[u8]) {
         let inverse = (char_code & 0x80) != 0;
         let char_code = char_code & 0x3F;
@@ -118,13 +160,13 @@ impl Video {
             return;
         }
 
-        let bitmap_addr = 0x1E00 + (char_code as usize * 8);
+        let bitmap_addr = self.charset_addr + (char_code as usize * 8);
 
         if bitmap_addr + 8 > rom.len() {
             return;
         }
 
-        let scale = ZX81_SCREEN_SF;
+        let scale = self.scale;
 
         // Calculate screen position
         let screen_x = col * 8 * scale;
@@ -161,7 +203,7 @@ impl Video {
     }
 
     fn render_debug_panel(&mut self, cpu: &Cpu, memory: &Memory) {
-        let panel_x = ZX81_SCREEN_WIDTH * ZX81_SCREEN_SF;
+        let panel_x = ZX81_SCREEN_WIDTH * self.scale;
         let colour = 0xFFFFFFFF; // White debug text 
         let bg_colour = 0xFF1A1A1A; // Dark-grey background
 
@@ -313,9 +355,9 @@ impl Video {
     fn draw_char(&mut self, ch: char, x: usize, y: usize, colour: u32) {
         let glyph = get_font_glyph(ch);
 
-        for row in 0..FONT_HEIGHT {
+        for (row, glyph_row) in glyph.iter().enumerate().take(FONT_HEIGHT) {
             for col in 0..FONT_WIDTH {
-                if (glyph[row] >> (4 - col)) & 1 != 0 {
+                if (glyph_row >> (4 - col)) & 1 != 0 {
                     for sy in 0..FONT_SCALE {
                         for sx in 0..FONT_SCALE {
                             let px = x + (col * FONT_SCALE) + sx;
@@ -332,17 +374,54 @@ impl Video {
     }
 
     pub fn update(&mut self) -> Result<(), minifb::Error> {
-        self.window
-            .update_with_buffer(&self.buffer, self.width, self.height)?;
+        if let Some(window) = &mut self.window {
+            window.update_with_buffer(&self.buffer, self.width, self.height)?;
+        }
         Ok(())
     }
 
+    // Take the window's input events without redrawing it
+    pub fn poll_input(&mut self) {
+        if let Some(window) = &mut self.window {
+            window.update();
+        }
+    }
+
+    // A headless video never closes, whoever drives it decides when to stop
     pub fn is_open(&self) -> bool {
-        self.window.is_open()
+        match &self.window {
+            Some(window) => window.is_open(),
+            None => true,
+        }
     }
 
     pub fn get_keys(&self) -> Vec<minifb::Key> {
-        self.window.get_keys()
+        match &self.window {
+            Some(window) => window.get_keys(),
+            None => Vec::new(),
+        }
+    }
+
+    // Keys that went down since the last update, for one-shot controls
+    pub fn get_keys_pressed(&self) -> Vec<minifb::Key> {
+        match &self.window {
+            Some(window) => window.get_keys_pressed(minifb::KeyRepeat::No),
+            None => Vec::new(),
+        }
+    }
+
+    // Characters typed since the last call, with the host's keyboard layout applied
+    pub fn take_typed(&self) -> Vec<char> {
+        std::mem::take(&mut *self.typed.borrow_mut())
+    }
+
+    // Rendered frame as 0xAARRGGBB pixels, row by row
+    pub fn buffer(&self) -> &[u32] {
+        &self.buffer
+    }
+
+    pub fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
     }
 }
 
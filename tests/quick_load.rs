@@ -0,0 +1,102 @@
+// Boots the bundled ZX81 ROM headless and checks a quick-load finishes the way the
+// ROM's own LOAD does
+use std::fs;
+use std::path::Path;
+
+use zx81_emulator::Emulator;
+use zx81_emulator::machine::{CLOCK_HZ, MachineConfig};
+
+const KEYBOARD: u16 = 0x02BB;
+const SLOW_FAST: u16 = 0x0207;
+const LINE_RUN: u16 = 0x0676;
+const FLAGS: u16 = 0x4001;
+const ERR_SP: u16 = 0x4002;
+const POKED: u16 = 0x7000; // Free RAM between the calculator stack and the machine stack
+
+fn emulator(rom: Vec<u8>, save_dir: &Path) -> Emulator {
+    let config = MachineConfig::builder(rom)
+        .headless(true)
+        .save_dir(save_dir)
+        .build();
+    Emulator::new(config).unwrap()
+}
+
+fn zx81_rom() -> Vec<u8> {
+    fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/zx81.rom")).unwrap()
+}
+
+// Step until PC reaches `addr`, giving up after `secs` of emulated time
+fn run_to(emulator: &mut Emulator, addr: u16, secs: u64) -> bool {
+    let limit = emulator.total_cycles() + secs * CLOCK_HZ;
+    while emulator.cpu().pc != addr {
+        if emulator.total_cycles() > limit {
+            return false;
+        }
+        emulator.step();
+    }
+    true
+}
+
+// A program saved by the ROM while running, so it runs on from line 20 when loaded
+fn saved_program(save_dir: &Path) -> Vec<u8> {
+    let mut emulator = emulator(zx81_rom(), save_dir);
+    assert!(run_to(&mut emulator, KEYBOARD, 10));
+    emulator
+        .type_text("10 SAVE \"QL\"\n20 POKE 28672,42\nRUN\n")
+        .unwrap();
+
+    let path = save_dir.join("QL.p");
+    let limit = emulator.total_cycles() + 60 * CLOCK_HZ;
+    while !path.exists() {
+        assert!(emulator.total_cycles() < limit, "SAVE never ran");
+        emulator.step();
+    }
+    fs::read(path).unwrap()
+}
+
+#[test]
+fn quick_load_runs_on_like_a_rom_load() {
+    let save_dir = std::env::temp_dir().join(format!("zx81-quick-load-{}", std::process::id()));
+    fs::create_dir_all(&save_dir).unwrap();
+    let data = saved_program(&save_dir);
+    fs::remove_dir_all(&save_dir).unwrap();
+
+    let mut emulator = emulator(zx81_rom(), &save_dir);
+    let sysvars = emulator.quick_load(&data).unwrap().unwrap();
+
+    // Everything from VERSN to E_LINE is in place
+    let memory = emulator.memory();
+    for (addr, &byte) in (0x4009..).zip(&data) {
+        assert_eq!(memory.read(addr), byte, "at {:04X}", addr);
+    }
+    assert_eq!(memory.read_word(0x400C), sysvars.d_file);
+    assert_eq!(memory.read_word(0x4010), sysvars.vars);
+    assert_eq!(memory.read_word(0x4014), sysvars.e_line);
+    let nxtlin = sysvars.autostart().expect("saved while running");
+    assert_eq!(memory.read(nxtlin + 1), 20);
+
+    // Left as LOAD would be leaving: running a command, about to return through
+    // SLOW/FAST to LINE-RUN from the error-return slot
+    assert_eq!(memory.read(FLAGS) & 0x80, 0x80);
+    assert_eq!(emulator.cpu().pc, SLOW_FAST);
+    assert_eq!(emulator.cpu().sp, memory.read_word(ERR_SP));
+    assert_eq!(memory.read_word(emulator.cpu().sp), LINE_RUN);
+
+    // Line 20 runs and the ROM goes back to waiting for keys
+    assert!(run_to(&mut emulator, LINE_RUN, 1));
+    assert!(run_to(&mut emulator, KEYBOARD, 10));
+    assert_eq!(emulator.memory().read(POKED), 42);
+}
+
+#[test]
+fn quick_load_gives_up_if_the_rom_never_reaches_the_prompt() {
+    // An 8K ROM that spins on the spot
+    let mut rom = vec![0; 0x2000];
+    rom[..2].copy_from_slice(&[0x18, 0xFE]);
+    let mut emulator = emulator(rom, &std::env::temp_dir());
+
+    assert!(emulator.quick_load(&[]).is_err());
+    // Given up after the 10 second cap, not before
+    assert!(emulator.total_cycles() >= 10 * CLOCK_HZ);
+    assert!(emulator.total_cycles() < 11 * CLOCK_HZ);
+}